@@ -0,0 +1,84 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use crate::syntax::Highlighter;
+use crate::textbuffer::TextBuffer;
+use crate::undo::{Cursor, UndoTree};
+
+/// A document open in the editor: its text, the file it belongs to, its undo
+/// history, and where it was last viewed so switching back to it restores
+/// the cursor and scroll position.
+#[derive(Debug)]
+pub struct Buffer {
+    /// Number shown by `:ls` and used by `:b N`, counting from 1.
+    pub id: usize,
+    pub row: TextBuffer,
+    pub filename: Option<String>,
+    pub path: Option<String>,
+    pub dirty: bool,
+    pub history: UndoTree,
+    pub undo_cursor: Cursor,
+    pub cursor: Cursor,
+    /// `(rowoff, coloff)` when the buffer was last shown.
+    pub offset: (usize, usize),
+    /// First and last row of the last visual selection a command was run
+    /// on, which `'<` and `'>` refer to.
+    pub visual: Option<(usize, usize)>,
+    pub syntax: Highlighter,
+}
+
+/// Writes `contents` to `path`, creating the folders it is in when they do
+/// not exist yet.
+pub fn write_file(path: &str, contents: String) -> io::Result<()> {
+    if let Some(folder) = Path::new(path).parent() {
+        std::fs::create_dir_all(folder)?;
+    }
+    std::fs::write(path, contents)
+}
+
+impl Buffer {
+    pub fn new(id: usize) -> Self {
+        Self {
+            id,
+            row: TextBuffer::new(),
+            filename: None,
+            path: None,
+            dirty: false,
+            history: UndoTree::new(),
+            undo_cursor: (0, 0),
+            cursor: (0, 0),
+            offset: (0, 0),
+            visual: None,
+            syntax: Highlighter::default(),
+        }
+    }
+
+    /// Loads `filename`, or starts an empty buffer for it when it does not
+    /// exist yet. The file is created by the first write.
+    pub fn open(id: usize, filename: &str) -> io::Result<Self> {
+        let mut buffer = Self::new(id);
+        buffer.set_path(filename);
+        if !Path::new(filename).exists() {
+            return Ok(buffer);
+        }
+
+        let f = File::open(filename)?;
+        buffer.row = TextBuffer::from_reader(BufReader::new(f))?;
+
+        Ok(buffer)
+    }
+
+    pub fn set_path(&mut self, path: &str) {
+        self.path = Some(path.to_string());
+        self.filename = Path::new(path)
+            .file_name()
+            .map(|os_str| os_str.to_string_lossy().into());
+        self.syntax = Highlighter::for_path(path);
+    }
+
+    /// An untouched buffer without a file, which opening a file can replace.
+    pub fn is_scratch(&self) -> bool {
+        self.path.is_none() && !self.dirty && self.row.is_empty()
+    }
+}
@@ -0,0 +1,147 @@
+use crossterm::QueueableCommand;
+use std::fmt;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Somewhere the `+` and `*` registers can send text to and read it from.
+pub trait ClipboardProvider: fmt::Debug {
+    fn name(&self) -> &str;
+    fn copy(&mut self, text: &str) -> io::Result<()>;
+    /// Current clipboard contents, or `None` when the backend can not read
+    /// the clipboard.
+    fn paste(&self) -> io::Result<Option<String>>;
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+/// Sets the clipboard of the terminal emulator itself with an OSC 52 escape
+/// sequence, which also works over SSH. Reading the clipboard back is not
+/// supported by most terminals, so `paste` always returns `None`.
+#[derive(Debug)]
+pub struct Osc52;
+
+impl ClipboardProvider for Osc52 {
+    fn name(&self) -> &str {
+        "osc52"
+    }
+
+    fn copy(&mut self, text: &str) -> io::Result<()> {
+        let mut sequence = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+        // tmux only forwards escape sequences wrapped in its own passthrough.
+        if std::env::var_os("TMUX").is_some() {
+            sequence = format!("\x1bPtmux;\x1b{sequence}\x1b\\");
+        }
+
+        io::stdout().queue(crossterm::style::Print(sequence))?;
+        Ok(())
+    }
+
+    fn paste(&self) -> io::Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// Shells out to external programs such as `xclip`, `wl-copy` or `pbcopy`.
+#[derive(Debug)]
+pub struct CommandClipboard {
+    copy: String,
+    paste: Option<String>,
+}
+
+impl CommandClipboard {
+    pub fn new(copy: &str, paste: Option<&str>) -> Self {
+        Self {
+            copy: copy.to_string(),
+            paste: paste.map(str::to_string),
+        }
+    }
+}
+
+impl ClipboardProvider for CommandClipboard {
+    fn name(&self) -> &str {
+        "command"
+    }
+
+    fn copy(&mut self, text: &str) -> io::Result<()> {
+        let mut child = Command::new("sh")
+            .args(["-c", &self.copy])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(text.as_bytes())?;
+
+        if child.wait()?.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("{} failed", self.copy)))
+        }
+    }
+
+    fn paste(&self) -> io::Result<Option<String>> {
+        let Some(paste) = &self.paste else {
+            return Ok(None);
+        };
+
+        let output = Command::new("sh")
+            .args(["-c", paste])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("{paste} failed")));
+        }
+
+        Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+    }
+}
+
+/// Builds the clipboard backend called `name`. The `command` backend needs a
+/// copy command, and optionally a paste command.
+pub fn provider(
+    name: &str,
+    copy: Option<&str>,
+    paste: Option<&str>,
+) -> Result<Box<dyn ClipboardProvider>, String> {
+    match name {
+        "osc52" => Ok(Box::new(Osc52)),
+        "command" => match copy {
+            Some(copy) => Ok(Box::new(CommandClipboard::new(copy, paste))),
+            None => Err(String::from("The command clipboard needs a copy command")),
+        },
+        _ => Err(format!("Unknown clipboard provider: {name}")),
+    }
+}
+
+/// Picks the clipboard backend from the environment, falling back to OSC 52.
+///
+/// `BREAD_CLIPBOARD` names the backend, `BREAD_CLIPBOARD_COPY` and
+/// `BREAD_CLIPBOARD_PASTE` are the commands used by the `command` backend.
+pub fn from_env() -> Result<Box<dyn ClipboardProvider>, String> {
+    let name = std::env::var("BREAD_CLIPBOARD").unwrap_or_else(|_| String::from("osc52"));
+    let copy = std::env::var("BREAD_CLIPBOARD_COPY").ok();
+    let paste = std::env::var("BREAD_CLIPBOARD_PASTE").ok();
+
+    provider(&name, copy.as_deref(), paste.as_deref())
+}
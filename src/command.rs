@@ -0,0 +1,598 @@
+use crate::keymap::MapMode;
+
+/// Line an address is relative to, before any `+N`/`-N` offset is applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Base {
+    /// An explicit 1-based line number.
+    Line(usize),
+    /// `.`, the cursor line.
+    Current,
+    /// `$`, the last line.
+    Last,
+    /// `'<` or `'>`, the first or last line of the last visual selection.
+    Mark(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Address {
+    pub base: Base,
+    pub offset: isize,
+}
+
+impl Address {
+    fn current() -> Self {
+        Self {
+            base: Base::Current,
+            offset: 0,
+        }
+    }
+
+    /// Resolves to a 0-based row, given the cursor row, the number of rows
+    /// and the rows of the last visual selection. Addresses before the first
+    /// line or on a mark that is not set resolve to `None`, later ones are
+    /// left for the caller to validate.
+    pub fn resolve(
+        &self,
+        cy: usize,
+        numrows: usize,
+        visual: Option<(usize, usize)>,
+    ) -> Option<usize> {
+        let line = match self.base {
+            Base::Line(n) => n as isize,
+            Base::Current => cy as isize + 1,
+            Base::Last => numrows as isize,
+            Base::Mark(mark) => {
+                let (first, last) = visual?;
+                (if mark == '<' { first } else { last }) as isize + 1
+            }
+        } + self.offset;
+
+        if line < 0 {
+            None
+        } else {
+            Some((line as usize).saturating_sub(1))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineRange {
+    pub start: Address,
+    pub end: Address,
+}
+
+impl LineRange {
+    fn current() -> Self {
+        Self {
+            start: Address::current(),
+            end: Address::current(),
+        }
+    }
+
+    /// Resolves to an inclusive pair of 0-based rows, failing when the range
+    /// falls outside the buffer.
+    pub fn resolve(
+        &self,
+        cy: usize,
+        numrows: usize,
+        visual: Option<(usize, usize)>,
+    ) -> Result<(usize, usize), String> {
+        let invalid = || String::from("Invalid range");
+        let start = self
+            .start
+            .resolve(cy, numrows, visual)
+            .ok_or_else(invalid)?;
+        let end = self.end.resolve(cy, numrows, visual).ok_or_else(invalid)?;
+        let (start, end) = if start > end {
+            (end, start)
+        } else {
+            (start, end)
+        };
+        if end >= numrows {
+            return Err(invalid());
+        }
+
+        Ok((start, end))
+    }
+}
+
+/// A parsed ex command line.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// `:<line>`, jumps to the last line of the range.
+    Goto(LineRange),
+    /// `:w [file]`
+    Write(Option<String>),
+    /// `:q` and `:q!`
+    Quit { force: bool },
+    /// `:wq[!] [file]`
+    WriteQuit { file: Option<String>, force: bool },
+    /// `:x[!] [file]`, writes only when there are unsaved changes.
+    Exit { file: Option<String>, force: bool },
+    /// `:e[!] file`
+    Edit { file: String, force: bool },
+    /// `:[range]d`
+    Delete(LineRange),
+    /// `:set [option[=value]]`
+    Set(Option<String>),
+    /// `:map`, `:nmap`, `:noremap` and the like. `args` is `lhs rhs`, or
+    /// just `lhs` to list the mappings starting with it.
+    Map {
+        modes: Vec<MapMode>,
+        remap: bool,
+        args: Option<String>,
+    },
+    /// `:noh`, hides search highlighting until the next search.
+    NoHighlight,
+    /// `:ls`, lists the open buffers.
+    ListBuffers,
+    /// `:bn[!]`
+    NextBuffer { force: bool },
+    /// `:bp[!]`
+    PreviousBuffer { force: bool },
+    /// `:b[!] N`
+    Buffer { id: usize, force: bool },
+    /// `:sp [file]`
+    Split(Option<String>),
+    /// `:vs [file]`
+    VerticalSplit(Option<String>),
+    /// `:clo[!]`
+    Close { force: bool },
+    /// `:on`, closes every other window.
+    Only,
+    /// `:tabnew [file]`
+    TabNew(Option<String>),
+    /// `:tabc[!]`
+    TabClose { force: bool },
+    /// A command registered by a script. Their names start with a capital
+    /// letter so they never clash with built in ones.
+    User { name: String, args: Option<String> },
+    /// `:[range]s/pattern/replacement/[flags]`
+    Substitute {
+        range: LineRange,
+        pattern: String,
+        replacement: String,
+        flags: String,
+    },
+}
+
+/// Splits the `/pattern/replacement/flags` argument of `:s`. Any char that is
+/// not alphanumeric, a backslash, `"` or `|` can be used instead of `/`, and a
+/// delimiter escaped with a backslash is taken literally.
+fn split_substitute(arg: &str) -> Result<(String, String, String), String> {
+    let mut chars = arg.chars();
+    let delimiter = match chars.next() {
+        Some(c) if !c.is_alphanumeric() && !matches!(c, '\\' | '"' | '|') => c,
+        _ => return Err(String::from("Usage: :s/pattern/replacement/[flags]")),
+    };
+
+    let mut fields = vec![String::new()];
+    while let Some(c) = chars.next() {
+        let full = fields.len() == 3;
+        let field = fields.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if next == delimiter => field.push(next),
+                Some(next) => {
+                    field.push('\\');
+                    field.push(next);
+                }
+                None => field.push('\\'),
+            },
+            c if c == delimiter && !full => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+
+    let mut fields = fields.into_iter();
+    let pattern = fields.next().unwrap_or_default();
+    let replacement = fields.next().unwrap_or_default();
+    let flags = fields.next().unwrap_or_default();
+    if let Some(flag) = flags.chars().find(|c| !matches!(c, 'g' | 'i' | 'c')) {
+        return Err(format!("Invalid substitute flag: {flag}"));
+    }
+
+    Ok((pattern, replacement, flags))
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+        }
+        self.input[start..self.pos].parse().ok()
+    }
+
+    fn address(&mut self) -> Option<Address> {
+        let base = match self.peek()? {
+            '.' => {
+                self.bump();
+                Some(Base::Current)
+            }
+            '$' => {
+                self.bump();
+                Some(Base::Last)
+            }
+            '\'' => {
+                self.bump();
+                match self.bump()? {
+                    mark @ ('<' | '>') => Some(Base::Mark(mark)),
+                    _ => return None,
+                }
+            }
+            c if c.is_ascii_digit() => self.number().map(Base::Line),
+            _ => None,
+        };
+
+        let mut offset = 0;
+        while let Some(sign @ ('+' | '-')) = self.peek() {
+            self.bump();
+            let n = self.number().unwrap_or(1) as isize;
+            offset += if sign == '+' { n } else { -n };
+        }
+
+        if base.is_none() && offset == 0 {
+            return None;
+        }
+
+        Some(Address {
+            base: base.unwrap_or(Base::Current),
+            offset,
+        })
+    }
+
+    fn range(&mut self) -> Option<LineRange> {
+        if self.peek() == Some('%') {
+            self.bump();
+            return Some(LineRange {
+                start: Address {
+                    base: Base::Line(1),
+                    offset: 0,
+                },
+                end: Address {
+                    base: Base::Last,
+                    offset: 0,
+                },
+            });
+        }
+
+        let start = self.address()?;
+        self.skip_whitespace();
+        if self.peek() == Some(',') {
+            self.bump();
+            self.skip_whitespace();
+            let end = self.address().unwrap_or(start);
+            return Some(LineRange { start, end });
+        }
+
+        Some(LineRange { start, end: start })
+    }
+
+    fn name(&mut self) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.bump();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn argument(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let arg = self.input[self.pos..].trim_end();
+        self.pos = self.input.len();
+        if arg.is_empty() {
+            None
+        } else {
+            Some(arg.to_string())
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Command, String> {
+    let mut parser = Parser { input, pos: 0 };
+    parser.skip_whitespace();
+    let range = parser.range();
+    parser.skip_whitespace();
+    let name = parser.name();
+    let force = parser.peek() == Some('!');
+    if force {
+        parser.bump();
+    }
+    let arg = parser.argument();
+
+    let command = match name {
+        "" => match range {
+            Some(range) if arg.is_none() => Command::Goto(range),
+            _ => return Err(format!("Not an editor command: {input}")),
+        },
+        "w" | "write" => Command::Write(arg),
+        "q" | "quit" => Command::Quit { force },
+        "wq" => Command::WriteQuit { file: arg, force },
+        "x" | "xit" => Command::Exit { file: arg, force },
+        "e" | "edit" => match arg {
+            Some(file) => Command::Edit { file, force },
+            None => return Err(String::from("No file name")),
+        },
+        "se" | "set" => Command::Set(arg),
+        "map" => Command::Map {
+            modes: vec![MapMode::Normal, MapMode::Visual],
+            remap: true,
+            args: arg,
+        },
+        "nm" | "nmap" => Command::Map {
+            modes: vec![MapMode::Normal],
+            remap: true,
+            args: arg,
+        },
+        "vm" | "vmap" => Command::Map {
+            modes: vec![MapMode::Visual],
+            remap: true,
+            args: arg,
+        },
+        "im" | "imap" => Command::Map {
+            modes: vec![MapMode::Insert],
+            remap: true,
+            args: arg,
+        },
+        "no" | "noremap" => Command::Map {
+            modes: vec![MapMode::Normal, MapMode::Visual],
+            remap: false,
+            args: arg,
+        },
+        "nn" | "nnoremap" => Command::Map {
+            modes: vec![MapMode::Normal],
+            remap: false,
+            args: arg,
+        },
+        "vn" | "vnoremap" => Command::Map {
+            modes: vec![MapMode::Visual],
+            remap: false,
+            args: arg,
+        },
+        "ino" | "inoremap" => Command::Map {
+            modes: vec![MapMode::Insert],
+            remap: false,
+            args: arg,
+        },
+        "noh" | "nohlsearch" => Command::NoHighlight,
+        "ls" | "buffers" | "files" => Command::ListBuffers,
+        "bn" | "bnext" => Command::NextBuffer { force },
+        "bp" | "bprevious" | "bN" | "bNext" => Command::PreviousBuffer { force },
+        "b" | "buffer" => match arg.as_deref().map(str::parse) {
+            Some(Ok(id)) => Command::Buffer { id, force },
+            Some(Err(_)) => return Err(format!("Invalid buffer number: {input}")),
+            None => return Err(String::from("Argument required")),
+        },
+        "sp" | "split" => Command::Split(arg),
+        "vs" | "vsplit" => Command::VerticalSplit(arg),
+        "clo" | "close" => Command::Close { force },
+        "on" | "only" => Command::Only,
+        "tabnew" => Command::TabNew(arg),
+        "tabc" | "tabclose" => Command::TabClose { force },
+        "s" | "substitute" => {
+            let (pattern, replacement, flags) = split_substitute(arg.as_deref().unwrap_or(""))?;
+            Command::Substitute {
+                range: range.unwrap_or_else(LineRange::current),
+                pattern,
+                replacement,
+                flags,
+            }
+        }
+        "d" | "delete" => Command::Delete(range.unwrap_or_else(LineRange::current)),
+        name if name.starts_with(|c: char| c.is_ascii_uppercase()) => Command::User {
+            name: name.to_string(),
+            args: arg,
+        },
+        _ => return Err(format!("Not an editor command: {input}")),
+    };
+
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(base: Base, offset: isize) -> Address {
+        Address { base, offset }
+    }
+
+    fn range(input: &str) -> LineRange {
+        match parse(input) {
+            Ok(Command::Delete(range)) => range,
+            other => panic!("{input}: {other:?}"),
+        }
+    }
+
+    fn substitute(input: &str) -> (String, String, String) {
+        match parse(input) {
+            Ok(Command::Substitute {
+                pattern,
+                replacement,
+                flags,
+                ..
+            }) => (pattern, replacement, flags),
+            other => panic!("{input}: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        let whole = range("%d");
+        assert_eq!(whole.start, address(Base::Line(1), 0));
+        assert_eq!(whole.end, address(Base::Last, 0));
+        assert_eq!(whole.resolve(4, 10, None), Ok((0, 9)));
+
+        let rest = range(".,$d");
+        assert_eq!(rest.start, address(Base::Current, 0));
+        assert_eq!(rest.end, address(Base::Last, 0));
+        assert_eq!(rest.resolve(4, 10, None), Ok((4, 9)));
+
+        let visual = range("'<,'>d");
+        assert_eq!(visual.start, address(Base::Mark('<'), 0));
+        assert_eq!(visual.end, address(Base::Mark('>'), 0));
+        assert_eq!(visual.resolve(0, 10, Some((2, 5))), Ok((2, 5)));
+        assert!(visual.resolve(0, 10, None).is_err());
+
+        assert_eq!(range("d"), LineRange::current());
+        assert_eq!(range(" 3 , 5 d").resolve(0, 10, None), Ok((2, 4)));
+        assert_eq!(range("5,3d").resolve(0, 10, None), Ok((2, 4)));
+        assert_eq!(range(".+2,$-1d").resolve(1, 10, None), Ok((3, 8)));
+        assert_eq!(range("-d").start, address(Base::Current, -1));
+        assert!(range("1,11d").resolve(0, 10, None).is_err());
+        assert!(range(".-5d").resolve(2, 10, None).is_err());
+    }
+
+    #[test]
+    fn goto() {
+        assert_eq!(
+            parse("42"),
+            Ok(Command::Goto(LineRange {
+                start: address(Base::Line(42), 0),
+                end: address(Base::Line(42), 0),
+            }))
+        );
+        assert!(parse("'x").is_err());
+    }
+
+    #[test]
+    fn substitute_patterns() {
+        let strings = |p: &str, r: &str, f: &str| (p.to_string(), r.to_string(), f.to_string());
+        assert_eq!(substitute("s/foo/bar/"), strings("foo", "bar", ""));
+        assert_eq!(substitute("s/foo/bar"), strings("foo", "bar", ""));
+        assert_eq!(substitute("s/foo"), strings("foo", "", ""));
+        assert_eq!(substitute(r"s/a\/b/c\/d/"), strings("a/b", "c/d", ""));
+        assert_eq!(substitute(r"s/\d+/\n/"), strings(r"\d+", r"\n", ""));
+        assert_eq!(substitute("s#/usr#/opt#g"), strings("/usr", "/opt", "g"));
+        assert_eq!(substitute(r"s#a\#b#c#"), strings("a#b", "c", ""));
+        assert_eq!(substitute("substitute/x/y/"), strings("x", "y", ""));
+
+        match parse("%s/a/b/") {
+            Ok(Command::Substitute { range, .. }) => {
+                assert_eq!(range.resolve(0, 3, None), Ok((0, 2)))
+            }
+            other => panic!("{other:?}"),
+        }
+        assert!(parse("s").is_err());
+        assert!(parse("s xaxbx").is_err());
+        assert!(parse(r"s\a\b\").is_err());
+    }
+
+    #[test]
+    fn substitute_flags() {
+        assert_eq!(substitute("s/a/b/g").2, "g");
+        assert_eq!(substitute("s/a/b/gic").2, "gic");
+        assert_eq!(substitute("s/a/b/ci").2, "ci");
+        assert_eq!(
+            parse("s/a/b/gx"),
+            Err(String::from("Invalid substitute flag: x"))
+        );
+        // A delimiter past the flags is not a flag.
+        assert!(parse("s/a/b/g/").is_err());
+    }
+
+    #[test]
+    fn forced_commands() {
+        assert_eq!(parse("q"), Ok(Command::Quit { force: false }));
+        assert_eq!(parse("q!"), Ok(Command::Quit { force: true }));
+        assert_eq!(parse("quit!"), Ok(Command::Quit { force: true }));
+        assert_eq!(
+            parse("wq!"),
+            Ok(Command::WriteQuit {
+                file: None,
+                force: true
+            })
+        );
+        assert_eq!(
+            parse("wq out.txt"),
+            Ok(Command::WriteQuit {
+                file: Some(String::from("out.txt")),
+                force: false
+            })
+        );
+        assert_eq!(
+            parse("x!"),
+            Ok(Command::Exit {
+                file: None,
+                force: true
+            })
+        );
+        assert_eq!(
+            parse("e! a.txt"),
+            Ok(Command::Edit {
+                file: String::from("a.txt"),
+                force: true
+            })
+        );
+        assert_eq!(parse("e!"), Err(String::from("No file name")));
+        assert_eq!(parse("bn!"), Ok(Command::NextBuffer { force: true }));
+        assert_eq!(parse("bp"), Ok(Command::PreviousBuffer { force: false }));
+        assert_eq!(parse("b! 2"), Ok(Command::Buffer { id: 2, force: true }));
+        assert!(parse("b two").is_err());
+        assert_eq!(parse("clo!"), Ok(Command::Close { force: true }));
+        assert_eq!(parse("tabc!"), Ok(Command::TabClose { force: true }));
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(parse("w"), Ok(Command::Write(None)));
+        assert_eq!(
+            parse("w  my file.txt  "),
+            Ok(Command::Write(Some(String::from("my file.txt"))))
+        );
+        assert_eq!(parse("set"), Ok(Command::Set(None)));
+        assert_eq!(
+            parse("se tabstop=4"),
+            Ok(Command::Set(Some(String::from("tabstop=4"))))
+        );
+        assert_eq!(
+            parse("nnoremap X gg<C-w>l"),
+            Ok(Command::Map {
+                modes: vec![MapMode::Normal],
+                remap: false,
+                args: Some(String::from("X gg<C-w>l")),
+            })
+        );
+        assert_eq!(
+            parse("map"),
+            Ok(Command::Map {
+                modes: vec![MapMode::Normal, MapMode::Visual],
+                remap: true,
+                args: None,
+            })
+        );
+        assert_eq!(
+            parse("Greet world"),
+            Ok(Command::User {
+                name: String::from("Greet"),
+                args: Some(String::from("world")),
+            })
+        );
+        assert_eq!(
+            parse("frobnicate"),
+            Err(String::from("Not an editor command: frobnicate"))
+        );
+    }
+}
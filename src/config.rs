@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+/// Name of the per-project config file, looked for in the current folder and
+/// the ones above it.
+const PROJECT_FILE: &str = ".bread.toml";
+
+/// How the line number gutter numbers rows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineNumbers {
+    #[default]
+    Off,
+    Absolute,
+    /// Distance from the cursor row.
+    Relative,
+    /// Relative, except for the cursor row which shows its own number.
+    Hybrid,
+}
+
+/// Key sequences mapped to actions or other keys, one table per mode. See
+/// `keymap::Binding::parse`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeymapConfig {
+    pub normal: BTreeMap<String, String>,
+    pub insert: BTreeMap<String, String>,
+    pub visual: BTreeMap<String, String>,
+}
+
+/// Which clipboard backend the `+` and `*` registers use. See
+/// `clipboard::provider`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClipboardConfig {
+    pub provider: String,
+    pub copy: Option<String>,
+    pub paste: Option<String>,
+}
+
+/// Options read from `config.toml`, which `:set` can change while running.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Cells between tab stops.
+    pub tabstop: usize,
+    /// Whether Tab and `>>` insert spaces instead of a tab.
+    pub expandtab: bool,
+    pub line_numbers: LineNumbers,
+    /// `default`, or the name of a theme in the `themes` folder of the
+    /// config folder.
+    pub theme: String,
+    /// Rows kept visible above and below the cursor.
+    pub scrolloff: usize,
+    /// Whether rows too long for the window go on over the next screen
+    /// lines instead of scrolling sideways.
+    pub wrap: bool,
+    /// Whether wrapped rows break after a blank rather than at any char.
+    pub linebreak: bool,
+    /// Drawn at the start of the screen lines a wrapped row goes on over.
+    pub showbreak: String,
+    /// Milliseconds to wait for the rest of a mapped key sequence.
+    pub timeoutlen: u64,
+    pub keymaps: KeymapConfig,
+    /// Overrides the `BREAD_CLIPBOARD*` environment variables.
+    pub clipboard: Option<ClipboardConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tabstop: 4,
+            expandtab: false,
+            line_numbers: LineNumbers::Off,
+            theme: String::from("default"),
+            scrolloff: 0,
+            wrap: false,
+            linebreak: false,
+            showbreak: String::new(),
+            timeoutlen: 1000,
+            keymaps: KeymapConfig::default(),
+            clipboard: None,
+        }
+    }
+}
+
+/// Short names `:set` accepts besides the full ones.
+const ALIASES: [(&str, &str); 6] = [
+    ("ts", "tabstop"),
+    ("et", "expandtab"),
+    ("so", "scrolloff"),
+    ("nu", "line_numbers"),
+    ("lbr", "linebreak"),
+    ("sbr", "showbreak"),
+];
+
+/// Options that hold a single value and so can be changed with `:set`.
+const OPTIONS: [&str; 9] = [
+    "tabstop",
+    "expandtab",
+    "line_numbers",
+    "theme",
+    "scrolloff",
+    "wrap",
+    "linebreak",
+    "showbreak",
+    "timeoutlen",
+];
+
+/// `$XDG_CONFIG_HOME/bread`, or `~/.config/bread` when it is not set.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("bread"))
+}
+
+/// The closest `.bread.toml` in the current folder or above.
+fn project_file() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Reads `path` as a TOML table. A missing file is an empty table.
+pub fn read_table(path: &Path) -> Result<Table, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Table::new()),
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+
+    text.parse::<Table>().map_err(|e| {
+        let line = e
+            .span()
+            .map(|span| text[..span.start].lines().count().max(1));
+        match line {
+            Some(line) => format!("{} line {line}: {}", path.display(), e.message()),
+            None => format!("{}: {}", path.display(), e.message()),
+        }
+    })
+}
+
+/// Copies the keys of `over` into `base`, merging tables key by key.
+fn merge(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Parses the value of `:set option=value`: TOML numbers and booleans as
+/// such, and anything else as a string.
+fn parse_value(value: &str) -> Value {
+    format!("value = {value}")
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+impl Config {
+    /// Loads the user config, then the project one over it. Any error is
+    /// returned along with the defaults to use instead.
+    pub fn load() -> (Self, Option<String>) {
+        let mut table = Table::new();
+        let files = config_dir()
+            .map(|dir| dir.join("config.toml"))
+            .into_iter()
+            .chain(project_file());
+        for path in files {
+            match read_table(&path) {
+                Ok(over) => merge(&mut table, over),
+                Err(msg) => return (Self::default(), Some(msg)),
+            }
+        }
+
+        let config = Self::deserialize(table)
+            .map_err(|e| format!("Config: {}", e.message()))
+            .and_then(Self::validate);
+        match config {
+            Ok(config) => (config, None),
+            Err(msg) => (Self::default(), Some(msg)),
+        }
+    }
+
+    fn validate(self) -> Result<Self, String> {
+        if self.tabstop == 0 {
+            return Err(String::from("tabstop must be at least 1"));
+        }
+        Ok(self)
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        let table = Table::try_from(self).ok()?;
+        match table.get(name)? {
+            Value::String(s) => Some(format!("{name}={s}")),
+            Value::Boolean(true) => Some(name.to_string()),
+            Value::Boolean(false) => Some(format!("no{name}")),
+            value => Some(format!("{name}={value}")),
+        }
+    }
+
+    /// Runs the argument of `:set`: `option=value`, `option` to turn a
+    /// boolean on or show a value, `nooption` to turn it off and `option?` to
+    /// show it. Returns the message to show, if any.
+    pub fn set(&mut self, arg: Option<&str>) -> Result<Option<String>, String> {
+        let Some(arg) = arg else {
+            let all: Vec<String> = OPTIONS.iter().filter_map(|name| self.get(name)).collect();
+            return Ok(Some(all.join("  ")));
+        };
+        // `line_numbers` has more values than on and off, but `nu` and `nonu`
+        // still switch it like vim's `number`.
+        let arg = match arg {
+            "nu" => "line_numbers=absolute",
+            "nonu" => "line_numbers=off",
+            arg => arg,
+        };
+
+        let canonical = |name: &str| {
+            let name = ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map_or(name, |(_, full)| full);
+            OPTIONS.iter().find(|option| **option == name).copied()
+        };
+        let unknown = || format!("Unknown option: {arg}");
+
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => {
+                let name = canonical(name.trim()).ok_or_else(unknown)?;
+                (name, parse_value(value.trim()))
+            }
+            None => {
+                let query = arg.strip_suffix('?');
+                let name = query.unwrap_or(arg);
+                if let Some(option) = canonical(name) {
+                    let is_bool = matches!(self.get(option), Some(ref v) if !v.contains('='));
+                    if query.is_some() || !is_bool {
+                        return Ok(self.get(option));
+                    }
+                    (option, Value::Boolean(true))
+                } else {
+                    let name = name.strip_prefix("no").and_then(canonical);
+                    (name.ok_or_else(unknown)?, Value::Boolean(false))
+                }
+            }
+        };
+
+        let mut table = Table::try_from(&*self).map_err(|e| e.to_string())?;
+        table.insert(name.to_string(), value);
+        *self = Self::deserialize(table)
+            .map_err(|e| format!("Invalid value for {name}: {}", e.message()))?
+            .validate()?;
+
+        Ok(None)
+    }
+}
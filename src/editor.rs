@@ -1,109 +1,153 @@
-use crate::{TABSTOP, VERSION};
-use crossterm::cursor::MoveTo;
-use crossterm::event::{poll, KeyCode, KeyEvent, KeyModifiers};
-use crossterm::style::{Color, Stylize};
+use crate::VERSION;
+use crossterm::event::{poll, Event, KeyCode, KeyEvent};
 use crossterm::terminal::ClearType;
 use crossterm::QueueableCommand;
-use std::fs::File;
-use std::io::{Read, Result};
-use std::path::Path;
-use std::time::Duration;
+use std::io::Result;
+use std::time::{Duration, Instant};
 use std::{fmt, io};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
+use crate::buffer::{self, Buffer};
+use crate::clipboard::{self, ClipboardProvider, Osc52};
+use crate::command::{self, Command};
+use crate::config::{Config, LineNumbers};
 use crate::keyboard::*;
+use crate::keymap::Keymaps;
+use crate::prompt::{Prompt, PromptAction};
+use crate::registers::Registers;
+use crate::screen::{Frame, Screen};
+use crate::script::{Hook, Scripts};
+use crate::search::{self, Direction, Pattern, SearchState};
+use crate::selection::{Selection, SelectionKind};
+use crate::substitute::Substitution;
+use crate::tab::TabPage;
 use crate::terminal::*;
+use crate::textbuffer::grapheme_len;
+use crate::theme::Theme;
+use crate::treesitter::Expansion;
+use crate::undo::Cursor;
+use crate::window::{Layout, Rect, SplitKind, Window};
 
-#[derive(Debug, PartialEq)]
+/// Extra presses of the quit key it takes to quit with unsaved changes.
+pub const QUIT_TIMES: u8 = 1;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EditorMode {
     NORMAL,
     INSERT,
+    COMMAND,
+    SEARCH,
+    VISUAL,
+    VISUALLINE,
+    VISUALBLOCK,
 }
 
 impl fmt::Display for EditorMode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            EditorMode::VISUALLINE => write!(f, "VISUAL LINE"),
+            EditorMode::VISUALBLOCK => write!(f, "VISUAL BLOCK"),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl EditorMode {
+    pub fn is_visual(&self) -> bool {
+        matches!(
+            self,
+            EditorMode::VISUAL | EditorMode::VISUALLINE | EditorMode::VISUALBLOCK
+        )
     }
 }
 
+/// How a cell of the text area is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Highlight {
+    Normal,
+    Comment,
+    Keyword,
+    Type,
+    String,
+    Number,
+    Function,
+    Match,
+    Selection,
+}
+
+/// Rendered view of a single row, built lazily from the text buffer when the
+/// row is about to be drawn. Render columns are terminal cells, so wide chars
+/// such as CJK and most emoji take two of them.
 #[derive(Debug)]
 pub struct Erow {
-    pub size: usize,
     pub chars: String,
     pub rsize: usize,
     pub render: String,
+    tabstop: usize,
 }
 
 impl Erow {
-    pub fn new() -> Self {
-        Self {
-            size: 0,
-            chars: String::from(""),
-            rsize: 0,
-            render: String::from(""),
-        }
-    }
-
-    pub fn from(chars: &str) -> Self {
-        Self {
-            size: chars.len(),
+    pub fn from(chars: &str, tabstop: usize) -> Self {
+        let mut erow = Self {
             chars: String::from(chars),
             rsize: 0,
             render: String::from(""),
-        }
-    }
-
-    pub fn delete_char(&mut self, at: usize) {
-        self.chars.remove(at - 1);
-        self.size -= 1;
-        self.editor_update_row();
+            tabstop,
+        };
+        erow.editor_update_row();
+        erow
     }
 
     pub fn editor_update_row(&mut self) {
         let mut render = String::new();
         let mut idx = 0;
-        for c in self.chars.chars() {
-            match c {
-                '\t' => {
-                    render.push(' ');
-                    idx += 1;
-                    while idx % TABSTOP != 0 {
-                        render.push(' ');
-                        idx += 1;
-                    }
-                }
-
-                _ => {
-                    render.push(c);
-                    idx += 1;
-                }
+        for g in self.chars.graphemes(true) {
+            let width = grapheme_width(g, idx, self.tabstop);
+            match g {
+                "\t" => render.push_str(&" ".repeat(width)),
+                // Control chars and lone zero width chars would not take up a
+                // cell of their own and throw the cursor off.
+                _ if g.width() == 0 || g.chars().any(char::is_control) => render.push('?'),
+                _ => render.push_str(g),
             }
+            idx += width;
         }
         self.rsize = idx;
         self.render = render;
     }
 
-    fn editor_row_cx_to_rx(&mut self, cx: usize) -> usize {
-        let mut rx = 0;
-        for i in 0..cx {
-            if let Some(c) = self.chars.chars().nth(i) {
-                if c == '\t' {
-                    rx += (TABSTOP - 1) - (rx % TABSTOP);
-                }
-                rx += 1;
+    /// Column of the grapheme drawn at render column `rx`, or the end of the
+    /// row when `rx` is past it.
+    pub fn editor_row_rx_to_cx(&self, rx: usize) -> usize {
+        let mut cur_rx = 0;
+        for (cx, g) in self.chars.graphemes(true).enumerate() {
+            cur_rx += grapheme_width(g, cur_rx, self.tabstop);
+            if cur_rx > rx {
+                return cx;
             }
         }
 
-        rx
+        grapheme_len(&self.chars)
     }
 
-    pub fn editor_row_insert_char(&mut self, at: usize, key: char) {
-        if at >= self.size {
-            self.chars.push(key)
-        } else {
-            self.chars.insert(at, key);
-        }
-        self.size += 1;
-        self.editor_update_row();
+    pub fn editor_row_cx_to_rx(&self, cx: usize) -> usize {
+        self.chars
+            .graphemes(true)
+            .take(cx)
+            .fold(0, |rx, g| rx + grapheme_width(g, rx, self.tabstop))
+    }
+}
+
+/// Number of cells grapheme `g` takes when drawn at render column `rx`.
+fn grapheme_width(g: &str, rx: usize, tabstop: usize) -> usize {
+    if g == "\t" {
+        tabstop - rx % tabstop
+    } else if g.chars().any(char::is_control) {
+        1
+    } else {
+        g.width().max(1)
     }
 }
 
@@ -114,20 +158,76 @@ pub struct EditorState {
     pub cy: usize,
     pub rx: usize,
     pub mode: EditorMode,
-    pub row: Vec<Erow>,
-    pub numrows: u16,
-    pub rowoff: u16,
-    pub coloff: u16,
-    pub filename: Option<String>,
-    path: Option<String>,
-    message: Option<String>,
-    pub dirty: bool,
+    /// The buffer being edited.
+    pub buffer: Buffer,
+    /// Every other open buffer, ordered by id.
+    buffers: Vec<Buffer>,
+    next_buffer_id: usize,
+    /// How the screen is divided between `windows`.
+    pub layout: Layout,
+    /// Every window on screen, in screen order.
+    pub windows: Vec<Window>,
+    /// Id of the window being edited.
+    pub window: usize,
+    pub next_window_id: usize,
+    /// Every tab page but the one on screen.
+    pub tabs: Vec<TabPage>,
+    /// Position of the tab page on screen among all of them.
+    pub tab: usize,
+    pub rowoff: usize,
+    pub coloff: usize,
+    pub message: Option<String>,
+    pub prompt: Prompt,
+    pub anchor: Cursor,
+    pub registers: Registers,
+    pub pending: PendingCommand,
+    pub search: SearchState,
+    pub substitute: Option<Substitution>,
+    pub config: Config,
+    pub keymaps: Keymaps,
+    /// Keys typed that may be the start of a mapping, each with whether
+    /// user mappings apply to it.
+    pub typed: Vec<(KeyEvent, bool)>,
+    pub typed_at: Instant,
+    /// Presses of the quit key still needed to quit with unsaved changes.
+    pub quit_times: u8,
+    pub scripts: Scripts,
+    /// Selections grown by expanding to a syntax node, for shrinking to go
+    /// back to.
+    pub expansions: Vec<Expansion>,
+    pub theme: Theme,
+    screen: Screen,
 }
 
 impl EditorState {
     pub fn new() -> Self {
-        let row = Vec::new();
-        let dimensions = resize_terminal();
+        let mut dimensions = resize_terminal();
+        // The last row of the terminal is the status line.
+        dimensions.rows = dimensions.rows.saturating_sub(1);
+        let (config, error) = Config::load();
+        let clipboard = match &config.clipboard {
+            Some(c) => clipboard::provider(&c.provider, c.copy.as_deref(), c.paste.as_deref()),
+            None => clipboard::from_env(),
+        };
+        let mut keymaps = Keymaps::new();
+        let error = error.or(keymaps.apply(&config.keymaps).err());
+        let (scripts, script_error) = Scripts::load();
+        let error = error.or(script_error);
+        let (theme, error) = match Theme::load(&config.theme) {
+            Ok(theme) => (theme, error),
+            Err(msg) => (Theme::default(), error.or(Some(msg))),
+        };
+        let (clipboard, message) = match clipboard {
+            Ok(clipboard) => (clipboard, error),
+            Err(msg) => (Box::new(Osc52) as Box<dyn ClipboardProvider>, Some(msg)),
+        };
+
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: dimensions.columns,
+            height: dimensions.rows,
+        };
 
         Self {
             dimensions,
@@ -135,31 +235,67 @@ impl EditorState {
             cy: 0,
             rx: 0,
             mode: EditorMode::NORMAL,
-            numrows: 0,
-            row,
+            buffer: Buffer::new(1),
+            buffers: Vec::new(),
+            next_buffer_id: 2,
+            layout: Layout::Window(1),
+            windows: vec![Window {
+                id: 1,
+                buffer: 1,
+                cursor: (0, 0),
+                offset: (0, 0),
+                rect,
+            }],
+            window: 1,
+            next_window_id: 2,
+            tabs: Vec::new(),
+            tab: 0,
             rowoff: 0,
             coloff: 0,
-            message: None,
-            filename: None,
-            path: None,
-            dirty: false,
+            message,
+            prompt: Prompt::new(),
+            anchor: (0, 0),
+            registers: Registers::new(clipboard),
+            pending: PendingCommand::default(),
+            search: SearchState::new(),
+            substitute: None,
+            config,
+            keymaps,
+            typed: Vec::new(),
+            typed_at: Instant::now(),
+            quit_times: QUIT_TIMES,
+            scripts,
+            expansions: Vec::new(),
+            theme,
+            screen: Screen::new(),
         }
     }
 
     fn editor_satus_message(&mut self) -> String {
-        if let Some(msg) = &self.message {
-            let padding_len = self.dimensions.columns as usize - msg.len();
+        if self.mode == EditorMode::COMMAND || self.mode == EditorMode::SEARCH {
+            let prompt = match self.mode {
+                EditorMode::SEARCH if self.search.direction == Direction::Backward => {
+                    format!("?{}", self.search.prompt.input)
+                }
+                EditorMode::SEARCH => format!("/{}", self.search.prompt.input),
+                _ => format!(":{}", self.prompt.input),
+            };
+            let padding_len = (self.dimensions.columns as usize).saturating_sub(prompt.width());
+            format!("{prompt}{:width$}", "", width = padding_len)
+        } else if let Some(msg) = &self.message {
+            let padding_len = (self.dimensions.columns as usize).saturating_sub(msg.width());
             let padding = format!("{:width$}", " ", width = padding_len);
             let status = format!("{msg}{}", padding);
             status
         } else {
             let status;
-            if let Some(filename) = &self.filename {
+            if let Some(filename) = &self.buffer.filename {
                 let status_content = format!("{} | {}", self.mode, filename);
                 let padding = format!(
                     "~{:width$}",
                     " ",
-                    width = self.dimensions.columns as usize - status_content.len() - 1
+                    width = (self.dimensions.columns as usize)
+                        .saturating_sub(status_content.width() + 1)
                 );
                 status = format!("{status_content}{}", padding);
                 status
@@ -168,7 +304,8 @@ impl EditorState {
                 let padding = format!(
                     "~{:width$}",
                     " ",
-                    width = self.dimensions.columns as usize - status_content.len() - 1
+                    width = (self.dimensions.columns as usize)
+                        .saturating_sub(status_content.width() + 1)
                 );
                 status = format!("{status_content}{}", padding);
                 status
@@ -176,272 +313,1097 @@ impl EditorState {
         }
     }
 
-    pub fn editor_status_line(&mut self) -> Result<()> {
-        let mut stdout = io::stdout();
-        let status = self
-            .editor_satus_message()
-            .with(Color::Black)
-            .on(Color::White);
-
-        stdout
-            .queue(crossterm::cursor::MoveTo(0, self.dimensions.rows + 1))?
-            .queue(crossterm::style::Print(status))?;
-
-        Ok(())
+    pub fn editor_status_line(&mut self, frame: &mut Frame) {
+        let status = self.editor_satus_message();
+        let style = self.theme.status_line;
+        let y = self.dimensions.rows as usize;
+        let x = frame.print(0, y, &status, style);
+        frame.fill(x, y, style);
     }
 
     pub fn erow_to_string(&self) -> String {
-        let mut buffer = String::new();
-        for i in 0..self.numrows {
-            buffer.push_str(&self.row[i as usize].chars);
-            buffer.push('\n');
-        }
-
-        buffer
+        self.buffer.row.to_string()
     }
 
     pub fn editor_save(&mut self) -> io::Result<()> {
-        if let Some(filepath) = &self.path {
+        if let Some(path) = self.buffer.path.clone() {
+            self.editor_run_hooks(Hook::Save, (path,));
+        }
+        if let Some(filepath) = &self.buffer.path {
             let buffer = self.erow_to_string();
-            std::fs::write(filepath, buffer)?;
-            self.dirty = false;
-            let msg = format!("{} has been saved!", self.filename.clone().unwrap());
+            buffer::write_file(filepath, buffer)?;
+            // The text written becomes an undo step of its own, so undoing
+            // back to it leaves the buffer unmodified.
+            self.editor_commit_undo();
+            self.buffer.undo_cursor = (self.cx, self.cy);
+            self.buffer.history.mark_saved();
+            self.buffer.dirty = false;
+            let msg = format!("{} has been saved!", self.buffer.filename.clone().unwrap());
             self.message = Some(msg);
-        } else {
         }
 
         Ok(())
     }
 
-    pub fn move_cursor(&mut self) {
-        crossterm::execute!(io::stdout(), MoveTo(self.cx as u16, self.cy as u16)).unwrap();
+    /// Writes the buffer to `filepath`, which becomes the buffer's file if it
+    /// does not have one yet.
+    pub fn editor_save_as(&mut self, filepath: &str) -> io::Result<()> {
+        if self.buffer.path.is_none() || self.buffer.path.as_deref() == Some(filepath) {
+            self.buffer.set_path(filepath);
+            return self.editor_save();
+        }
+
+        self.editor_run_hooks(Hook::Save, (filepath.to_string(),));
+        buffer::write_file(filepath, self.erow_to_string())?;
+        self.message = Some(format!("{filepath} has been saved!"));
+        Ok(())
     }
 
-    pub fn process_char(&mut self) -> io::Result<bool> {
-        static mut QUIT_TIMES: u8 = 1;
-        match poll(Duration::from_millis(100)) {
-            Ok(true) => match read_character() {
-                Some(key) => match key {
-                    KeyEvent {
-                        code: KeyCode::Char('q'),
-                        modifiers: KeyModifiers::CONTROL,
-                        ..
-                    } => {
-                        if self.dirty && unsafe { QUIT_TIMES } > 0 {
-                            // TODO STATUS MESSAGE
-                            unsafe { QUIT_TIMES -= 1 }
-                        } else {
-                            let mut stdout = io::stdout();
-                            stdout.queue(crossterm::cursor::SetCursorStyle::SteadyBlock)?;
-                            return Ok(true);
+    fn editor_write(&mut self, file: Option<String>) {
+        let result = match file {
+            Some(file) => self.editor_save_as(&file),
+            None if self.buffer.path.is_some() => self.editor_save(),
+            None => {
+                self.message = Some(String::from("No file name"));
+                return;
+            }
+        };
+
+        if let Err(e) = result {
+            self.message = Some(format!("Could not write file: {e}"));
+        }
+    }
+
+    /// Feeds a key to the command line prompt. Returns true when the editor
+    /// should quit.
+    fn editor_prompt_key(&mut self, key: KeyEvent) -> bool {
+        match self.prompt.handle_key(key) {
+            PromptAction::Edit => false,
+            PromptAction::Cancel => {
+                self.mode = EditorMode::NORMAL;
+                false
+            }
+            PromptAction::Submit(input) => {
+                self.mode = EditorMode::NORMAL;
+                self.editor_run_command(&input)
+            }
+        }
+    }
+
+    /// Parses and executes an ex command. Returns true when the editor should
+    /// quit.
+    pub fn editor_run_command(&mut self, input: &str) -> bool {
+        let command = match command::parse(input) {
+            Ok(command) => command,
+            Err(msg) => {
+                self.message = Some(msg);
+                return false;
+            }
+        };
+
+        match command {
+            Command::Goto(range) => {
+                if !self.buffer.row.is_empty() {
+                    let line = range
+                        .end
+                        .resolve(self.cy, self.buffer.row.len(), self.buffer.visual)
+                        .unwrap_or(0);
+                    self.cy = line.min(self.buffer.row.len() - 1);
+                    self.cx = 0;
+                }
+            }
+            Command::Write(file) => self.editor_write(file),
+            // With several windows, quitting only closes the current one.
+            Command::Quit { force } if self.editor_has_other_windows() => {
+                self.editor_close_window(force)
+            }
+            Command::Quit { force } => return self.editor_can_quit(force),
+            Command::WriteQuit { file, force } => {
+                self.editor_write(file);
+                return self.editor_quit_written(force);
+            }
+            Command::Exit { file, force } => {
+                if self.buffer.dirty || file.is_some() {
+                    self.editor_write(file);
+                }
+                return self.editor_quit_written(force);
+            }
+            Command::Edit { file, force } => {
+                if self.buffer.path.as_deref() == Some(file.as_str()) {
+                    if force {
+                        self.editor_reload();
+                    }
+                } else if self.editor_can_abandon(force) {
+                    self.editor_open(&file);
+                }
+            }
+            Command::ListBuffers => self.message = Some(self.editor_list_buffers()),
+            Command::NextBuffer { force } => {
+                if self.editor_can_abandon(force) {
+                    self.editor_cycle_buffer(1);
+                }
+            }
+            Command::PreviousBuffer { force } => {
+                if self.editor_can_abandon(force) {
+                    self.editor_cycle_buffer(-1);
+                }
+            }
+            Command::Buffer { id, force } => {
+                if id != self.buffer.id && self.editor_can_abandon(force) {
+                    self.editor_switch_buffer(id);
+                }
+            }
+            Command::Split(file) => self.editor_split(SplitKind::Horizontal, file),
+            Command::VerticalSplit(file) => self.editor_split(SplitKind::Vertical, file),
+            Command::Close { force } => self.editor_close_window(force),
+            Command::Only => self.editor_only_window(),
+            Command::TabNew(file) => self.editor_new_tab(file),
+            Command::TabClose { force } => self.editor_close_tab(force),
+            Command::Set(arg) => match self.config.set(arg.as_deref()) {
+                Ok(msg) => {
+                    self.message = msg;
+                    if self.config.theme != self.theme.name {
+                        self.editor_load_theme();
+                    }
+                }
+                Err(msg) => self.message = Some(msg),
+            },
+            Command::Map { modes, remap, args } => self.editor_map(&modes, remap, args.as_deref()),
+            Command::User { name, args } => self.editor_run_user_command(&name, args.as_deref()),
+            Command::NoHighlight => self.search.highlight = false,
+            Command::Substitute {
+                range,
+                pattern,
+                replacement,
+                flags,
+            } => self.editor_substitute(range, &pattern, &replacement, &flags),
+            Command::Delete(range) => {
+                match range.resolve(self.cy, self.buffer.row.len(), self.buffer.visual) {
+                    Ok((start, end)) => {
+                        self.buffer.row.remove_lines(start, end + 1);
+                        self.buffer.dirty = true;
+                        self.cy = start.min(self.buffer.row.len().saturating_sub(1));
+                        self.cx = 0;
+                        let count = end + 1 - start;
+                        if count > 2 {
+                            self.message = Some(format!("{count} fewer lines"));
                         }
                     }
+                    Err(msg) => self.message = Some(msg),
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Opens the `/` or `?` prompt, remembering where the search started.
+    pub fn editor_start_search(&mut self, direction: Direction) {
+        self.mode = EditorMode::SEARCH;
+        self.search.direction = direction;
+        self.search.prompt.clear();
+        self.search.origin = ((self.cx, self.cy), self.rowoff, self.coloff);
+    }
+
+    /// Pattern whose matches are highlighted: the one being typed, or else
+    /// the last search.
+    fn editor_search_pattern(&self) -> Option<Pattern> {
+        if self.mode == EditorMode::SEARCH {
+            if self.search.prompt.input.is_empty() {
+                return None;
+            }
+            return Pattern::new(&self.search.prompt.input).ok();
+        }
 
-                    KeyEvent {
-                        code: KeyCode::Esc, ..
-                    } => self.mode = EditorMode::NORMAL,
-
-                    KeyEvent {
-                        code: KeyCode::Char('s'),
-                        modifiers: KeyModifiers::CONTROL,
-                        ..
-                    } => self.editor_save()?,
-
-                    KeyEvent {
-                        code: KeyCode::Backspace,
-                        ..
-                    } => {
-                        self.dirty = true;
-
-                        if self.mode == EditorMode::INSERT {
-                            if self.cx > 0 {
-                                self.row[self.cy].delete_char(self.cx);
-                                self.cx -= 1;
-                            } else if self.cx == 0 && self.cy > 0 {
-                                let buffer = &self.row[self.cy].chars.clone();
-                                self.row[self.cy - 1].chars.push_str(buffer);
-
-                                //self.row[self.cy - 1].chars.push('\n');
-                                self.row.remove(self.cy);
-                                self.numrows -= 1;
-                                self.row[self.cy - 1].editor_update_row();
-                                self.cy -= 1;
-                                self.cx = self.row[self.cy - 1].rsize;
-                            }
-                        } else if self.mode == EditorMode::NORMAL && self.cx > 0 {
-                            self.cx -= 1;
+        if self.search.highlight {
+            self.search.last.clone()
+        } else {
+            None
+        }
+    }
+
+    /// Moves the cursor to the next match of `pattern` from `from`, reporting
+    /// when the search wraps or finds nothing.
+    fn editor_search_from(
+        &mut self,
+        pattern: &Pattern,
+        from: Cursor,
+        direction: Direction,
+    ) -> bool {
+        match search::find(&self.buffer.row, pattern, from, direction) {
+            Some(found) => {
+                (self.cx, self.cy) = found.at;
+                self.message = if !found.wrapped {
+                    None
+                } else if direction == Direction::Forward {
+                    Some(String::from("search hit BOTTOM, continuing at TOP"))
+                } else {
+                    Some(String::from("search hit TOP, continuing at BOTTOM"))
+                };
+                true
+            }
+            None => {
+                self.message = Some(format!("Pattern not found: {}", pattern.text));
+                false
+            }
+        }
+    }
+
+    /// Jumps to the first match as the pattern is typed.
+    fn editor_search_update(&mut self) {
+        let (origin, rowoff, coloff) = self.search.origin;
+        (self.cx, self.cy) = origin;
+        (self.rowoff, self.coloff) = (rowoff, coloff);
+        if let Ok(pattern) = Pattern::new(&self.search.prompt.input) {
+            self.editor_search_from(&pattern, origin, self.search.direction);
+        }
+        self.message = None;
+    }
+
+    fn editor_search_key(&mut self, key: KeyEvent) {
+        let (origin, rowoff, coloff) = self.search.origin;
+        match self.search.prompt.handle_key(key) {
+            PromptAction::Edit => self.editor_search_update(),
+            PromptAction::Cancel => {
+                self.mode = EditorMode::NORMAL;
+                (self.cx, self.cy) = origin;
+                (self.rowoff, self.coloff) = (rowoff, coloff);
+            }
+            PromptAction::Submit(input) => {
+                self.mode = EditorMode::NORMAL;
+                (self.cx, self.cy) = origin;
+                // An empty pattern repeats the last search.
+                let pattern = if input.is_empty() {
+                    match self.search.last.clone() {
+                        Some(pattern) => pattern,
+                        None => {
+                            self.message = Some(String::from("No previous search pattern"));
+                            return;
                         }
                     }
-
-                    KeyEvent {
-                        code: KeyCode::Enter,
-                        ..
-                    } => {
-                        if self.mode == EditorMode::NORMAL {
-                            if self.cy <= self.numrows.into() {
-                                self.cy += 1;
-                            }
-                        } else if self.mode == EditorMode::INSERT {
-                            let buffer = Erow::from(&self.row[self.cy].chars[self.cx..]);
-                            self.row[self.cy].chars.insert(self.cx, '\n');
-                            self.row.insert(self.cy + 1, buffer);
-                            self.numrows += 1;
-                            self.row[self.cy].editor_update_row();
-                            self.row[self.cy + 1].editor_update_row();
-                            self.cy += 1;
-                            self.cx = 0;
+                } else {
+                    match Pattern::new(&input) {
+                        Ok(pattern) => pattern,
+                        Err(msg) => {
+                            self.message = Some(msg);
+                            (self.rowoff, self.coloff) = (rowoff, coloff);
+                            return;
                         }
                     }
+                };
+                self.editor_set_last_search(pattern.clone());
+                if !self.editor_search_from(&pattern, origin, self.search.direction) {
+                    (self.rowoff, self.coloff) = (rowoff, coloff);
+                }
+            }
+        }
+    }
 
-                    KeyEvent {
-                        code: KeyCode::Char(c),
-                        ..
-                    } => {
-                        self.message = None;
-                        if self.mode == EditorMode::NORMAL {
-                            normal_mode_shortcuts(self, c);
-                        } else if self.mode == EditorMode::INSERT {
-                            self.editor_insert_char(c);
-                        }
+    fn editor_set_last_search(&mut self, pattern: Pattern) {
+        self.registers.last_search = Some(pattern.text.clone());
+        self.search.last = Some(pattern);
+        self.search.highlight = true;
+    }
+
+    /// `n` and `N`: repeats the last search, in the opposite direction when
+    /// `reverse` is set.
+    pub fn editor_search_next(&mut self, reverse: bool, count: usize) {
+        let Some(pattern) = self.search.last.clone() else {
+            self.message = Some(String::from("No previous search pattern"));
+            return;
+        };
+
+        let direction = if reverse {
+            self.search.direction.reverse()
+        } else {
+            self.search.direction
+        };
+        self.search.highlight = true;
+        for _ in 0..count.max(1) {
+            if !self.editor_search_from(&pattern, (self.cx, self.cy), direction) {
+                break;
+            }
+        }
+    }
+
+    /// `*` and `#`: searches for the word under the cursor.
+    pub fn editor_search_word(&mut self, direction: Direction, count: usize) {
+        if self.buffer.row.is_empty() {
+            return;
+        }
+        let Some((start, word)) = search::word_at(&self.buffer.row.line(self.cy), self.cx) else {
+            self.message = Some(String::from("No identifier under cursor"));
+            return;
+        };
+
+        self.search.direction = direction;
+        self.editor_set_last_search(Pattern::word(&word));
+        self.cx = start;
+        self.editor_search_next(false, count);
+    }
+
+    /// Enters the visual `mode` from NORMAL mode, switches between visual modes,
+    /// or leaves visual mode when already in `mode`. An empty buffer has
+    /// nothing to select, so none of them start there.
+    pub fn editor_toggle_visual(&mut self, mode: EditorMode) {
+        if self.buffer.row.is_empty() && !self.mode.is_visual() {
+            return;
+        }
+        if self.mode == mode {
+            self.mode = EditorMode::NORMAL;
+            return;
+        }
+
+        if !self.mode.is_visual() {
+            self.anchor = (self.cx, self.cy);
+        }
+        self.mode = mode;
+    }
+
+    /// Opens the command line. From visual mode it starts with `'<,'>`, the
+    /// selected lines.
+    pub fn editor_command_line(&mut self) {
+        self.prompt.clear();
+        if let Some(selection) = self.editor_selection() {
+            self.buffer.visual = Some(selection.rows());
+            self.prompt.insert("'<,'>");
+        }
+        self.mode = EditorMode::COMMAND;
+    }
+
+    /// The active visual selection, if any.
+    pub fn editor_selection(&self) -> Option<Selection> {
+        let kind = match self.mode {
+            EditorMode::VISUAL => SelectionKind::Charwise,
+            EditorMode::VISUALLINE => SelectionKind::Linewise,
+            EditorMode::VISUALBLOCK => SelectionKind::Blockwise,
+            _ => return None,
+        };
+
+        if self.buffer.row.is_empty() {
+            return None;
+        }
+
+        Some(Selection::new(kind, self.anchor, (self.cx, self.cy)))
+    }
+
+    /// Where the terminal cursor should be drawn.
+    pub fn editor_cursor_position(&self) -> (u16, u16) {
+        if self.mode == EditorMode::COMMAND {
+            return ((self.prompt.width() + 1) as u16, self.dimensions.rows);
+        }
+        if self.mode == EditorMode::SEARCH {
+            return (
+                (self.search.prompt.width() + 1) as u16,
+                self.dimensions.rows,
+            );
+        }
+
+        let text = self.editor_text_area();
+        let (column, line) = self.editor_cursor_cell();
+        (text.x + column as u16, text.y + line as u16)
+    }
+
+    /// Handles the next terminal event, if any. Returns true when the editor
+    /// should quit.
+    pub fn process_char(&mut self) -> io::Result<bool> {
+        let mode = self.mode;
+        let quit = self.editor_process_event()?;
+        if self.mode != mode && !quit {
+            let args = (self.mode.to_string(), mode.to_string());
+            self.editor_run_hooks(Hook::ModeChange, args);
+        }
+        Ok(quit)
+    }
+
+    fn editor_process_event(&mut self) -> io::Result<bool> {
+        let quit = if let Ok(true) = poll(Duration::from_millis(100)) {
+            let key = match read_event()? {
+                Event::Key(key) => key,
+                Event::Resize(columns, rows) => {
+                    self.editor_resize(columns, rows);
+                    return Ok(false);
+                }
+                Event::Mouse(event) => {
+                    self.editor_mouse(event);
+                    return Ok(false);
+                }
+                Event::Paste(text) => {
+                    self.editor_paste(&text);
+                    return Ok(false);
+                }
+                _ => return Ok(false),
+            };
+
+            if !self.buffer.row.has_pending_edits() {
+                self.buffer.undo_cursor = (self.cx, self.cy);
+            }
+
+            self.editor_feed_key(key)?
+        } else if self.editor_keys_timed_out() {
+            self.editor_resolve_keys(true)?
+        } else {
+            return Ok(false);
+        };
+
+        // A whole INSERT mode session, or a confirmed substitution, is
+        // grouped into a single undo step.
+        if self.mode != EditorMode::INSERT && self.substitute.is_none() {
+            self.editor_commit_undo();
+        }
+
+        if quit {
+            let mut stdout = io::stdout();
+            stdout.queue(crossterm::cursor::SetCursorStyle::SteadyBlock)?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Handles a key that is not mapped to anything. Returns true when the
+    /// editor should quit.
+    pub fn editor_handle_key(&mut self, key: KeyEvent) -> io::Result<bool> {
+        let mut quit = false;
+        match key {
+            key if self.substitute.is_some() => self.editor_confirm_key(key),
+            key if self.mode == EditorMode::COMMAND => quit = self.editor_prompt_key(key),
+            key if self.mode == EditorMode::SEARCH => self.editor_search_key(key),
+
+            KeyEvent {
+                code: KeyCode::Esc, ..
+            } => {
+                self.mode = EditorMode::NORMAL;
+                self.pending = PendingCommand::default();
+            }
+
+            KeyEvent {
+                code: KeyCode::Backspace,
+                ..
+            } => {
+                self.buffer.dirty = true;
+
+                if self.mode == EditorMode::INSERT {
+                    if self.cx > 0 {
+                        self.buffer.row.delete_char(self.cy, self.cx - 1);
+                        self.cx -= 1;
+                    } else if self.cx == 0 && self.cy > 0 {
+                        self.cx = self.buffer.row.line_len(self.cy - 1);
+                        self.buffer.row.join_lines(self.cy - 1);
+                        self.cy -= 1;
                     }
-                    _ => {}
-                },
-                None => {}
-            },
+                } else if self.mode == EditorMode::NORMAL && self.cx > 0 {
+                    self.cx -= 1;
+                }
+            }
+
+            KeyEvent {
+                code: KeyCode::Enter,
+                ..
+            } => {
+                if self.mode == EditorMode::NORMAL {
+                    if self.cy + 1 < self.buffer.row.len() {
+                        self.cy += 1;
+                    }
+                } else if self.mode == EditorMode::INSERT {
+                    self.buffer.row.split_line(self.cy, self.cx);
+                    self.cy += 1;
+                    self.cx = 0;
+                }
+            }
+
+            KeyEvent {
+                code: KeyCode::Tab, ..
+            } if self.mode == EditorMode::INSERT => self.editor_insert_tab(),
+
+            KeyEvent {
+                code: KeyCode::Char(c),
+                ..
+            } => {
+                self.message = None;
+                self.quit_times = QUIT_TIMES;
+                if self.mode == EditorMode::NORMAL {
+                    normal_mode_shortcuts(self, c);
+                } else if self.mode.is_visual() {
+                    visual_mode_shortcuts(self, c);
+                } else if self.mode == EditorMode::INSERT {
+                    self.editor_insert_char(c);
+                }
+            }
             _ => {}
         }
 
-        Ok(false)
+        Ok(quit)
     }
 
-    pub fn editor_draw_rows(&self) -> Result<()> {
-        let mut stdout = io::stdout();
-        for i in 0..self.dimensions.rows {
-            let filerow = i + self.rowoff;
-            if filerow >= self.numrows {
-                if i == self.dimensions.rows / 3 && self.numrows == 0 {
+    fn editor_commit_undo(&mut self) {
+        let edits = self.buffer.row.take_edits();
+        self.buffer
+            .history
+            .record(edits, self.buffer.undo_cursor, (self.cx, self.cy));
+    }
+
+    pub fn editor_undo(&mut self) {
+        match self.buffer.history.undo(&mut self.buffer.row) {
+            Some(cursor) => self.editor_restore_cursor(cursor),
+            None => self.message = Some(String::from("Already at oldest change")),
+        }
+    }
+
+    pub fn editor_redo(&mut self) {
+        match self.buffer.history.redo(&mut self.buffer.row) {
+            Some(cursor) => self.editor_restore_cursor(cursor),
+            None => self.message = Some(String::from("Already at newest change")),
+        }
+    }
+
+    fn editor_restore_cursor(&mut self, (cx, cy): Cursor) {
+        self.buffer.dirty = !self.buffer.history.is_saved();
+        self.cy = cy.min(self.buffer.row.len().saturating_sub(1));
+        self.cx = if self.buffer.row.is_empty() {
+            0
+        } else {
+            cx.min(self.buffer.row.line_len(self.cy))
+        };
+    }
+
+    /// Cells taken by the line number gutter of a window `width` cells wide
+    /// showing a buffer of `rows` rows: the widest number, at least three
+    /// digits, and a space.
+    pub fn editor_gutter_width(&self, rows: usize, width: u16) -> usize {
+        if self.config.line_numbers == LineNumbers::Off {
+            return 0;
+        }
+        (rows.to_string().len().max(3) + 1).min(width as usize)
+    }
+
+    /// Draws the text of `buffer`, scrolled by `(rowoff, coloff)`, into
+    /// `rect`, after a gutter numbering rows around the cursor row `cy`. With
+    /// `wrap` on, rows go on over as many screen lines as they need.
+    pub fn editor_draw_rows(
+        &self,
+        frame: &mut Frame,
+        rect: Rect,
+        buffer: &Buffer,
+        (rowoff, coloff): (usize, usize),
+        cy: usize,
+        selection: Option<Selection>,
+    ) {
+        let pattern = self.editor_search_pattern();
+        let gutter = self.editor_gutter_width(buffer.row.len(), rect.width);
+        let left = rect.x as usize + gutter;
+        let columns = rect.width as usize - gutter;
+        let bottom = (rect.y + rect.height) as usize;
+        let mut y = rect.y as usize;
+        let mut filerow = rowoff;
+        while y < bottom {
+            if filerow >= buffer.row.len() {
+                if y - rect.y as usize == rect.height as usize / 3 && buffer.row.is_empty() {
                     let welcome_str = format!("BREAD EDITOR - VERSION : {VERSION}");
-                    let w = (self.dimensions.columns as usize - welcome_str.len()) / 2;
-                    let padding = format!("{:width$}", " ", width = w);
-                    stdout
-                        .queue(crossterm::style::Print(padding))?
-                        .queue(crossterm::style::Print(welcome_str))?;
+                    let welcome_str = &welcome_str[..welcome_str.len().min(columns)];
+                    let w = columns.saturating_sub(welcome_str.len()) / 2;
+                    frame.print(left + w, y, welcome_str, self.theme.non_text);
+                } else if rect.width > 0 {
+                    frame.print(rect.x as usize, y, "~", self.theme.non_text);
+                }
+                y += 1;
+                continue;
+            }
+
+            if gutter > 0 {
+                let number = match self.config.line_numbers {
+                    LineNumbers::Relative => filerow.abs_diff(cy),
+                    LineNumbers::Hybrid if filerow != cy => filerow.abs_diff(cy),
+                    _ => filerow + 1,
+                };
+                let style = if filerow == cy {
+                    self.theme.line_number_current
                 } else {
-                    stdout.queue(crossterm::style::Print("\r\n~"))?;
+                    self.theme.line_number
+                };
+                let label = format!("{number:>width$} ", width = gutter - 1);
+                frame.print(rect.x as usize, y, &label[label.len() - gutter..], style);
+            }
+            let erow = Erow::from(&buffer.row.line(filerow), self.config.tabstop);
+            let line_len = buffer.row.line_len(filerow);
+            // One entry per render column, plus one for the end of the line.
+            let mut hl = vec![Highlight::Normal; erow.rsize + 1];
+            let mut rx = 0;
+            for (g, syntax) in erow.chars.graphemes(true).zip(buffer.syntax.row(filerow)) {
+                let width = grapheme_width(g, rx, self.config.tabstop);
+                hl[rx..rx + width].fill(*syntax);
+                rx += width;
+            }
+            if let Some(pattern) = &pattern {
+                for (s, e) in pattern.matches(&erow.chars) {
+                    let hs = erow.editor_row_cx_to_rx(s);
+                    let he = erow.editor_row_cx_to_rx(e);
+                    hl[hs..he].fill(Highlight::Match);
                 }
+            }
+            let span = selection.and_then(|selection| selection.span(filerow, line_len));
+            if let Some((s, e)) = span {
+                let hs = erow.editor_row_cx_to_rx(s);
+                let he = if e > line_len {
+                    erow.rsize + 1
+                } else {
+                    erow.editor_row_cx_to_rx(e)
+                };
+                hl[hs..he].fill(Highlight::Selection);
+            }
+
+            // Each screen line shows the render columns `start..end`, after
+            // `indent` cells of break indicator.
+            let lines: Vec<(usize, usize, usize)> = if self.config.wrap {
+                let showbreak = self.editor_showbreak_width(columns);
+                let starts = self.editor_wrap_starts(&erow, columns);
+                starts
+                    .iter()
+                    .enumerate()
+                    .map(|(k, &start)| {
+                        let indent = if k == 0 { 0 } else { showbreak };
+                        let end = starts.get(k + 1).copied();
+                        (start, end.unwrap_or(start + columns - indent), indent)
+                    })
+                    .collect()
             } else {
-                let mut len = self.row[filerow as usize].rsize;
-                if len < self.coloff as usize {
-                    continue;
+                vec![(coloff, coloff + columns, 0)]
+            };
+            for (start, end, indent) in lines {
+                if y >= bottom {
+                    break;
                 }
-                len -= self.coloff as usize;
-                let start = self.coloff as usize;
-                let end = start
-                    + if len >= self.dimensions.columns as usize {
-                        self.dimensions.columns as usize
-                    } else {
-                        len
-                    };
-                stdout
-                    .queue(crossterm::cursor::MoveTo(0, i))?
-                    .queue(crossterm::terminal::Clear(ClearType::CurrentLine))?
-                    .queue(crossterm::style::Print(
-                        &self.row[filerow as usize].render[start..end],
-                    ))?;
+                if indent > 0 {
+                    frame.print(left, y, &self.config.showbreak, self.theme.non_text);
+                }
+                let left = left + indent;
+                let mut col = 0;
+                for g in erow.render.graphemes(true) {
+                    let width = g.width().max(1);
+                    if col + width > end {
+                        // A wide char cut by the right edge is left out.
+                        break;
+                    }
+                    if col >= start {
+                        frame.put(
+                            left + col - start,
+                            y,
+                            g,
+                            width,
+                            self.theme.highlight(hl[col]),
+                        );
+                    } else if col + width > start {
+                        // Only the right half of a wide char is on screen.
+                        let blank = " ".repeat(col + width - start);
+                        frame.print(left, y, &blank, self.theme.highlight(hl[col]));
+                    }
+                    col += width;
+                }
+                // A selected newline is drawn as a cell past the end of the text.
+                if col == erow.rsize
+                    && (start..end).contains(&col)
+                    && hl[col] == Highlight::Selection
+                {
+                    frame.put(
+                        left + col - start,
+                        y,
+                        " ",
+                        1,
+                        self.theme.highlight(Highlight::Selection),
+                    );
+                }
+                y += 1;
             }
+            filerow += 1;
         }
-        Ok(())
     }
 
     pub fn editor_insert_char(&mut self, key: char) {
-        if self.dirty == false {
-            self.dirty = true
+        self.buffer.dirty = true;
+
+        if self.cy == self.buffer.row.len() {
+            self.editor_append_row("");
         }
+        // A combining char joins the grapheme before it instead of adding a
+        // column.
+        let len = self.buffer.row.line_len(self.cy);
+        self.buffer.row.insert_char(self.cy, self.cx, key);
+        self.cx += self.buffer.row.line_len(self.cy) - len;
+    }
+
+    /// What one level of indentation inserts: a tab, or with `expandtab` as
+    /// many spaces as a tab is wide.
+    pub fn editor_indent_unit(&self) -> String {
+        if self.config.expandtab {
+            " ".repeat(self.config.tabstop)
+        } else {
+            String::from("\t")
+        }
+    }
+
+    /// Inserts a tab, or with `expandtab` the spaces up to the next tab stop.
+    fn editor_insert_tab(&mut self) {
+        if !self.config.expandtab {
+            return self.editor_insert_char('\t');
+        }
+
+        if self.buffer.row.is_empty() {
+            self.editor_append_row("");
+        }
+        let line = self.buffer.row.line(self.cy);
+        let rx = Erow::from(&line, self.config.tabstop).editor_row_cx_to_rx(self.cx);
+        let spaces = self.config.tabstop - rx % self.config.tabstop;
+        self.buffer
+            .row
+            .insert_str(self.cy, self.cx, &" ".repeat(spaces));
+        self.cx += spaces;
+        self.buffer.dirty = true;
+    }
 
-        if self.cy == self.numrows.into() {
-            self.editor_append_row(String::new(), 0);
+    /// Inserts text from a bracketed paste in one go, as a single undo step,
+    /// instead of replaying it as key presses.
+    pub fn editor_paste(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        match self.mode {
+            EditorMode::COMMAND => self.prompt.insert(text.lines().next().unwrap_or("")),
+            EditorMode::SEARCH => {
+                self.search.prompt.insert(text.lines().next().unwrap_or(""));
+                self.editor_search_update();
+            }
+            _ => {
+                // Keep what was typed before the paste in its own undo step.
+                self.editor_commit_undo();
+                self.buffer.undo_cursor = (self.cx, self.cy);
+                if self.buffer.row.is_empty() {
+                    self.editor_append_row("");
+                }
+
+                let len = self.buffer.row.line_len(self.cy);
+                self.buffer.row.insert_str(self.cy, self.cx, &text);
+                let added = text.matches('\n').count();
+                if added > 0 {
+                    self.cy += added;
+                    self.cx = grapheme_len(text.rsplit('\n').next().unwrap_or(""));
+                } else {
+                    self.cx += self.buffer.row.line_len(self.cy) - len;
+                }
+                // NORMAL mode leaves the cursor on the last pasted char.
+                if self.mode != EditorMode::INSERT {
+                    self.cx = self.cx.saturating_sub(1);
+                }
+                self.buffer.dirty = true;
+                self.editor_commit_undo();
+            }
         }
-        self.row[self.cy].editor_row_insert_char(self.cx, key);
-        self.cx += 1;
     }
 
     pub fn change_cursor(&self) -> Result<()> {
         let mut stdout = io::stdout();
         if self.mode == EditorMode::NORMAL {
             stdout.queue(crossterm::cursor::SetCursorStyle::SteadyBlock)?;
-        } else if self.mode == EditorMode::INSERT {
+        } else if matches!(
+            self.mode,
+            EditorMode::INSERT | EditorMode::COMMAND | EditorMode::SEARCH
+        ) {
             stdout.queue(crossterm::cursor::SetCursorStyle::BlinkingBar)?;
         }
         Ok(())
     }
 
-    pub fn refresh_screen(&mut self) {
+    /// Paints the windows and status line into a new frame and draws
+    /// whatever changed since the last one.
+    pub fn refresh_screen(&mut self) -> Result<()> {
         self.editor_scroll();
-        clear().unwrap();
-        self.editor_draw_rows().unwrap();
+        self.editor_update_syntax();
+        let mut frame = Frame::new(
+            self.dimensions.columns,
+            self.dimensions.rows + 1,
+            self.theme.normal,
+        );
+        if !self.tabs.is_empty() {
+            self.editor_draw_tabline(&mut frame);
+        }
+        self.editor_draw_windows(&mut frame);
+        self.editor_status_line(&mut frame);
+        let cursor = self.editor_cursor_position();
+        self.screen.render(frame, cursor)
     }
 
-    pub fn editor_append_row(&mut self, chars: String, length: usize) {
-        let loc = self.numrows as usize;
-        self.row[loc].size = length;
-        self.row[loc].chars = chars;
-        self.numrows += 1;
+    pub fn editor_append_row(&mut self, chars: &str) {
+        self.buffer.row.insert_line(self.buffer.row.len(), chars);
+    }
 
-        self.row[loc].rsize = 0;
-        self.row[loc].render = String::new();
-        self.row[loc].editor_update_row();
+    /// Adapts to a terminal that is now `columns` by `rows` cells.
+    pub fn editor_resize(&mut self, columns: u16, rows: u16) {
+        self.dimensions = WindowSize {
+            rows: rows.saturating_sub(1),
+            columns,
+        };
+        self.editor_layout();
+        // Scroll back up if the window grew past the end of the buffer.
+        let rows = self.editor_text_area().height as usize;
+        if self.rowoff + rows > self.buffer.row.len() {
+            self.rowoff = self.buffer.row.len().saturating_sub(rows).min(self.cy);
+        }
+        self.coloff = 0;
+        self.editor_scroll();
     }
 
     pub fn editor_scroll(&mut self) {
         self.rx = 0;
 
-        if self.cy < self.numrows.into() {
-            self.rx = self.row[self.cy].editor_row_cx_to_rx(self.cx);
+        if self.cy < self.buffer.row.len() {
+            self.rx = Erow::from(&self.buffer.row.line(self.cy), self.config.tabstop)
+                .editor_row_cx_to_rx(self.cx);
         }
 
-        if self.cy < self.rowoff.into() {
-            self.rowoff = self.cy as u16;
+        let text = self.editor_text_area();
+        let rows = (text.height as usize).max(1);
+        // Keep `scrolloff` rows around the cursor, short of scrolling past
+        // either end of the buffer.
+        let scrolloff = self.config.scrolloff.min((rows - 1) / 2);
+        if self.config.wrap {
+            self.editor_scroll_wrapped(rows, scrolloff);
+            return;
         }
-
-        if self.rx < self.coloff.into() {
-            self.coloff = self.rx as u16;
+        let top = self.cy.saturating_sub(scrolloff);
+        let bottom = (self.cy + scrolloff).min(self.buffer.row.len().saturating_sub(1));
+        if top < self.rowoff {
+            self.rowoff = top;
+        }
+        if bottom.max(self.cy) >= self.rowoff + rows {
+            self.rowoff = bottom.max(self.cy) - rows + 1;
         }
 
-        if self.cy >= (self.rowoff + self.dimensions.rows) as usize {
-            self.rowoff = (self.cy as u16 - self.dimensions.rows + 1) as u16;
+        if self.rx < self.coloff {
+            self.coloff = self.rx;
         }
 
-        if self.rx >= (self.coloff + self.dimensions.columns) as usize {
-            self.coloff = (self.rx as u16 - self.dimensions.columns + 1) as u16;
+        let columns = (text.width as usize).max(1);
+        if self.rx >= self.coloff + columns {
+            self.coloff = self.rx - columns + 1;
         }
     }
 
+    /// Opens `filename` in a new buffer and switches to it, or switches to
+    /// the buffer already holding it.
     pub fn editor_open(&mut self, filename: &str) {
-        if let Ok(mut f) = File::open(filename) {
-            let mut buffer = String::new();
-            f.read_to_string(&mut buffer).unwrap();
-
-            self.path = Some(filename.to_string());
-            self.filename = Path::new(filename)
-                .file_name()
-                .map(|os_str| os_str.to_string_lossy().into());
-
-            buffer.lines().for_each(|l| {
-                let new_row = Erow::new();
-                self.row.push(new_row);
-                self.editor_append_row(l.to_string(), l.len());
-            });
+        let path = Some(filename.to_string());
+        if self.buffer.path == path {
+            return;
+        }
+        if let Some(id) = self
+            .buffers
+            .iter()
+            .find(|buffer| buffer.path == path)
+            .map(|buffer| buffer.id)
+        {
+            self.editor_switch_buffer(id);
+            return;
+        }
+
+        // The empty buffer the editor starts with is replaced, unless another
+        // window shows it.
+        let id = if self.buffer.is_scratch() && !self.editor_buffer_shown_elsewhere() {
+            self.buffer.id
         } else {
-            let folders = Path::new(filename)
-                .parent()
-                .map(|parent| parent.to_str().unwrap());
-            if let Some(folder) = folders {
-                std::fs::create_dir_all(folder).unwrap();
+            self.next_buffer_id += 1;
+            self.next_buffer_id - 1
+        };
+        match Buffer::open(id, filename) {
+            Ok(buffer) => {
+                self.editor_store_view();
+                let previous = std::mem::replace(&mut self.buffer, buffer);
+                if previous.id != id {
+                    self.editor_hide_buffer(previous);
+                }
+                self.editor_restore_view();
+                self.editor_run_hooks(Hook::Open, (filename.to_string(),));
             }
-            std::fs::write(filename, "").unwrap();
-            self.editor_open(filename);
+            Err(e) => self.message = Some(format!("Could not open {filename}: {e}")),
+        }
+    }
+
+    /// Switches to a new empty buffer.
+    pub fn editor_new_buffer(&mut self) {
+        self.editor_store_view();
+        let buffer = Buffer::new(self.next_buffer_id);
+        self.next_buffer_id += 1;
+        let previous = std::mem::replace(&mut self.buffer, buffer);
+        self.editor_hide_buffer(previous);
+        self.editor_restore_view();
+    }
+
+    /// Reads the file of the current buffer again, dropping any changes.
+    fn editor_reload(&mut self) {
+        let Some(path) = self.buffer.path.clone() else {
+            self.message = Some(String::from("No file name"));
+            return;
+        };
+
+        match Buffer::open(self.buffer.id, &path) {
+            Ok(buffer) => {
+                self.buffer = buffer;
+                self.cy = self.cy.min(self.buffer.row.len().saturating_sub(1));
+                self.cx = 0;
+            }
+            Err(e) => self.message = Some(format!("Could not open {path}: {e}")),
+        }
+    }
+
+    pub fn editor_store_view(&mut self) {
+        self.buffer.cursor = (self.cx, self.cy);
+        self.buffer.offset = (self.rowoff, self.coloff);
+    }
+
+    fn editor_restore_view(&mut self) {
+        (self.cx, self.cy) = self.buffer.cursor;
+        (self.rowoff, self.coloff) = self.buffer.offset;
+    }
+
+    fn editor_hide_buffer(&mut self, buffer: Buffer) {
+        let idx = self.buffers.partition_point(|b| b.id < buffer.id);
+        self.buffers.insert(idx, buffer);
+    }
+
+    /// Buffer `id`, whether it is the current one or not.
+    pub fn editor_buffer(&self, id: usize) -> &Buffer {
+        if id == self.buffer.id {
+            return &self.buffer;
+        }
+        self.buffers
+            .iter()
+            .find(|buffer| buffer.id == id)
+            .unwrap_or(&self.buffer)
+    }
+
+    pub fn editor_buffer_mut(&mut self, id: usize) -> &mut Buffer {
+        if id == self.buffer.id {
+            return &mut self.buffer;
+        }
+        match self.buffers.iter_mut().find(|buffer| buffer.id == id) {
+            Some(buffer) => buffer,
+            None => &mut self.buffer,
+        }
+    }
+
+    /// Makes buffer `id` the current one.
+    pub fn editor_switch_buffer(&mut self, id: usize) {
+        if id == self.buffer.id {
+            return;
+        }
+        if !self.buffers.iter().any(|buffer| buffer.id == id) {
+            self.message = Some(format!("Buffer {id} does not exist"));
+            return;
+        }
+
+        self.editor_store_view();
+        self.editor_swap_buffer(id);
+        self.editor_restore_view();
+    }
+
+    /// Makes buffer `id` the current one, leaving the cursor alone.
+    pub fn editor_swap_buffer(&mut self, id: usize) {
+        if let Some(idx) = self.buffers.iter().position(|buffer| buffer.id == id) {
+            let buffer = self.buffers.remove(idx);
+            let previous = std::mem::replace(&mut self.buffer, buffer);
+            self.editor_hide_buffer(previous);
         }
     }
+
+    /// Switches to the buffer `step` places after (or before, when negative)
+    /// the current one, wrapping around.
+    fn editor_cycle_buffer(&mut self, step: isize) {
+        if self.buffers.is_empty() {
+            return;
+        }
+
+        let mut ids: Vec<usize> = self.buffers.iter().map(|buffer| buffer.id).collect();
+        let idx = ids.partition_point(|&id| id < self.buffer.id);
+        ids.insert(idx, self.buffer.id);
+        let len = ids.len() as isize;
+        let next = (idx as isize + step).rem_euclid(len) as usize;
+        self.editor_switch_buffer(ids[next]);
+    }
+
+    /// One line summary of the open buffers, like vim's `:ls`: `%a` marks
+    /// the current buffer and `+` one with unsaved changes.
+    fn editor_list_buffers(&self) -> String {
+        let mut buffers: Vec<&Buffer> = self.buffers.iter().collect();
+        let idx = buffers.partition_point(|b| b.id < self.buffer.id);
+        buffers.insert(idx, &self.buffer);
+
+        buffers
+            .iter()
+            .map(|buffer| {
+                let current = if buffer.id == self.buffer.id {
+                    "%a"
+                } else {
+                    ""
+                };
+                let dirty = if buffer.dirty { "+" } else { "" };
+                let name = buffer.filename.as_deref().unwrap_or("[No Name]");
+                format!("{}{current}{dirty} \"{name}\"", buffer.id)
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+    /// Refuses to leave a buffer with unsaved changes unless forced.
+    pub fn editor_can_abandon(&mut self, force: bool) -> bool {
+        if self.buffer.dirty && !force {
+            self.message = Some(String::from(
+                "No write since last change (add ! to override)",
+            ));
+            return false;
+        }
+
+        true
+    }
+
+    /// The quit key: quits unless some buffer has unsaved changes, in which
+    /// case it has to be pressed `QUIT_TIMES` more times in a row.
+    pub fn editor_quit_key(&mut self) -> bool {
+        if self.quit_times == 0 || self.editor_can_quit(false) {
+            return true;
+        }
+        let msg = self.message.take().unwrap_or_default();
+        self.message = Some(format!("{msg}; quit again to discard"));
+        self.quit_times -= 1;
+        false
+    }
+
+    /// Whether the editor may quit, which it may not while any buffer has
+    /// unsaved changes, unless forced.
+    fn editor_can_quit(&mut self, force: bool) -> bool {
+        if !self.editor_can_abandon(force) {
+            return false;
+        }
+        if let Some(buffer) = self.buffers.iter().find(|buffer| buffer.dirty && !force) {
+            let name = buffer.filename.as_deref().unwrap_or("[No Name]");
+            self.message = Some(format!(
+                "No write since last change for buffer {} \"{name}\"",
+                buffer.id
+            ));
+            return false;
+        }
+
+        true
+    }
+
+    /// Quits after `:wq` or `:x` wrote the current buffer, as `:q` would.
+    /// Does nothing when the write failed.
+    fn editor_quit_written(&mut self, force: bool) -> bool {
+        if self.buffer.dirty {
+            return false;
+        }
+        if self.editor_has_other_windows() {
+            self.editor_close_window(force);
+            return false;
+        }
+
+        self.editor_can_quit(force)
+    }
 }
 
 pub fn clear() -> Result<()> {
@@ -1,190 +1,245 @@
 use std::io;
 
-use crossterm::{
-    event::{read, Event::Key},
-    terminal::ClearType,
-    QueueableCommand,
-};
+use crossterm::event::{read, Event};
 
 use crate::editor::*;
+use crate::keymap::Action;
+use crate::motion::Motion;
+use crate::operator::Operator;
+use crate::registers;
+use crate::search::Direction;
 
-pub fn read_character() -> Option<crossterm::event::KeyEvent> {
-    if let Ok(e) = read() {
-        if let Key(key_event) = e {
-            return Some(key_event);
+/// Blocks until the next terminal event, be it a key press or a resize.
+pub fn read_event() -> io::Result<Event> {
+    read()
+}
+
+/// Keys typed so far in NORMAL mode that do not form a complete command yet,
+/// e.g. the `2d` of `2d3w`.
+#[derive(Debug, Default)]
+pub struct PendingCommand {
+    count: Option<usize>,
+    operator: Option<(Operator, char)>,
+    operator_count: Option<usize>,
+    g_prefix: bool,
+    register_prefix: bool,
+    register: Option<char>,
+}
+
+impl PendingCommand {
+    /// The total count for the command, multiplying the counts typed before
+    /// and after the operator.
+    fn take_count(&mut self) -> Option<usize> {
+        match (self.operator_count.take(), self.count.take()) {
+            (Some(a), Some(b)) => Some(a * b),
+            (a, b) => a.or(b),
         }
     }
-    panic!("read failed");
+
+    /// Whether the next key finishes an operator, register name or `g`
+    /// command instead of starting a command of its own, and so is not
+    /// looked up in the keymap.
+    pub fn is_partial(&self) -> bool {
+        self.operator.is_some() || self.g_prefix || self.register_prefix
+    }
 }
 
-pub fn normal_mode_shortcuts(terminal_state: &mut EditorState, key: char) {
-    match key {
-        'i' => {
-            terminal_state.mode = EditorMode::INSERT;
-            if terminal_state.numrows == 0 {
-                // This is to prevent out of bounds error when we create a new file and try to append text to it.
-                let new_row = Erow::new();
-                terminal_state.row.push(new_row);
-                let mut stdout = io::stdout();
-                stdout
-                    .queue(crossterm::terminal::Clear(ClearType::All))
-                    .unwrap();
-                terminal_state.editor_append_row(String::new(), 0);
-                terminal_state.row[terminal_state.cy].editor_update_row();
-            }
+fn operator_for(key: char) -> Option<Operator> {
+    let operator = match key {
+        'd' => Operator::Delete,
+        'y' => Operator::Yank,
+        'c' => Operator::Change,
+        '>' => Operator::Indent,
+        '<' => Operator::Outdent,
+        _ => return None,
+    };
+
+    Some(operator)
+}
+
+/// Handles count digits and motions, which behave the same in NORMAL and
+/// visual modes. Returns false when `key` is neither.
+fn count_or_motion(terminal_state: &mut EditorState, key: char) -> bool {
+    let pending = &mut terminal_state.pending;
+    if pending.register_prefix {
+        pending.register_prefix = false;
+        if registers::is_valid_name(key) {
+            pending.register = Some(key);
+        } else {
+            terminal_state.pending = PendingCommand::default();
         }
-        '$' => {
-            if (terminal_state.row[terminal_state.cy as usize].size) > 0 {
-                terminal_state.cx = (terminal_state.row[terminal_state.cy].size) - 1;
-            } else {
-                terminal_state.cx = 0;
+        return true;
+    }
+
+    if key == '"' && pending.operator.is_none() {
+        pending.register_prefix = true;
+        return true;
+    }
+
+    if key.is_ascii_digit() && (key != '0' || pending.count.is_some()) {
+        let digit = key.to_digit(10).unwrap() as usize;
+        pending.count = Some(pending.count.unwrap_or(0) * 10 + digit);
+        return true;
+    }
+
+    if key == 'g' && !pending.g_prefix {
+        pending.g_prefix = true;
+        return true;
+    }
+
+    let motion = if pending.g_prefix {
+        pending.g_prefix = false;
+        match key {
+            'g' => Some(Motion::FirstLine),
+            'j' => Some(Motion::ScreenDown),
+            'k' => Some(Motion::ScreenUp),
+            _ => {
+                terminal_state.pending = PendingCommand::default();
+                return true;
             }
+        }
+    } else {
+        Motion::from_key(key)
+    };
+    let Some(motion) = motion else {
+        return false;
+    };
 
-            terminal_state.move_cursor();
+    let count = pending.take_count();
+    match pending.operator.take() {
+        Some((operator, _)) => {
+            let register = pending.register.take();
+            terminal_state.editor_operate_motion(operator, motion, count, register);
         }
-        '_' => {
-            terminal_state.cx = 0;
-            terminal_state.move_cursor();
+        None => {
+            (terminal_state.cx, terminal_state.cy) =
+                terminal_state.editor_motion_target(motion, count);
         }
-        'w' => {
-            // REALLY DISGUSTING CODE
-            if terminal_state.row[terminal_state.cy]
-                .chars
-                .chars()
-                .nth(terminal_state.cx)
-                .unwrap()
-                .is_whitespace()
-                && !terminal_state.row[terminal_state.cy]
-                    .chars
-                    .chars()
-                    .nth(terminal_state.cx + 1)
-                    .unwrap()
-                    .is_whitespace()
-            {
-                terminal_state.cx += 1;
+    }
+
+    true
+}
+
+pub fn normal_mode_shortcuts(terminal_state: &mut EditorState, key: char) {
+    if count_or_motion(terminal_state, key) {
+        return;
+    }
+
+    if let Some(operator) = operator_for(key) {
+        let pending = &mut terminal_state.pending;
+        match pending.operator {
+            // Doubling an operator (`dd`, `yy`, `>>`) applies it to whole lines.
+            Some((_, pending_key)) if pending_key == key => {
+                pending.operator = None;
+                let count = pending.take_count().unwrap_or(1);
+                let register = pending.register.take();
+                terminal_state.editor_operate_lines(operator, count, register);
             }
-            if terminal_state.row[terminal_state.cy]
-                .chars
-                .chars()
-                .nth(terminal_state.cx)
-                .unwrap()
-                .is_whitespace()
-                || terminal_state.row[terminal_state.cy]
-                    .chars
-                    .chars()
-                    .nth(terminal_state.cx + 1)
-                    .unwrap()
-                    .is_whitespace()
-            {
-                while terminal_state.row[terminal_state.cy]
-                    .chars
-                    .chars()
-                    .nth(terminal_state.cx + 1)
-                    .unwrap()
-                    .is_whitespace()
-                {
-                    terminal_state.cx += 1;
-                }
-            } else {
-                let mut iter = terminal_state.row[terminal_state.cy].chars[terminal_state.cx..]
-                    .split_whitespace();
-
-                iter.next();
-                if let Some(n_word) = iter.next() {
-                    terminal_state.cx = terminal_state.row[terminal_state.cy].chars
-                        [terminal_state.cx..]
-                        .find(n_word)
-                        .unwrap()
-                        + terminal_state.cx;
-                }
+            Some(_) => terminal_state.pending = PendingCommand::default(),
+            None => {
+                pending.operator = Some((operator, key));
+                pending.operator_count = pending.count.take();
             }
         }
-        'b' => {
-            if terminal_state.row[terminal_state.cy]
-                .chars
-                .chars()
-                .nth(terminal_state.cx)
-                .unwrap()
-                .is_whitespace()
-                && !terminal_state.row[terminal_state.cy]
-                    .chars
-                    .chars()
-                    .nth(terminal_state.cx - 1)
-                    .unwrap()
-                    .is_whitespace()
-            {
-                terminal_state.cx -= 1;
-            }
-            if terminal_state.row[terminal_state.cy]
-                .chars
-                .chars()
-                .nth(terminal_state.cx)
-                .unwrap()
-                .is_whitespace()
-                || terminal_state.row[terminal_state.cy]
-                    .chars
-                    .chars()
-                    .nth(terminal_state.cx - 1)
-                    .unwrap()
-                    .is_whitespace()
-            {
-                while terminal_state.row[terminal_state.cy]
-                    .chars
-                    .chars()
-                    .nth(terminal_state.cx - 1)
-                    .unwrap()
-                    .is_whitespace()
-                {
-                    terminal_state.cx -= 1;
-                }
-            } else {
-                let mut iter = terminal_state.row[terminal_state.cy].chars[..terminal_state.cx]
-                    .split_whitespace();
-
-                iter.next();
-                if let Some(n_word) = iter.next() {
-                    terminal_state.cx = terminal_state.row[terminal_state.cy].chars
-                        [..terminal_state.cx]
-                        .find(n_word)
-                        .unwrap()
-                }
+        return;
+    }
+
+    // Any other key cancels a pending operator.
+    terminal_state.pending = PendingCommand::default();
+}
+
+/// Runs a named action from a keymap. Returns true when the editor should
+/// quit.
+pub fn run_action(terminal_state: &mut EditorState, action: Action) -> io::Result<bool> {
+    terminal_state.message = None;
+    let count = terminal_state.pending.take_count();
+    let register = terminal_state.pending.register.take();
+    terminal_state.pending = PendingCommand::default();
+    if action != Action::Quit {
+        terminal_state.quit_times = QUIT_TIMES;
+    }
+
+    match action {
+        Action::Quit => return Ok(terminal_state.editor_quit_key()),
+        Action::Save => terminal_state.editor_save()?,
+        Action::Undo => terminal_state.editor_undo(),
+        Action::Redo => terminal_state.editor_redo(),
+        Action::InsertMode => {
+            terminal_state.mode = EditorMode::INSERT;
+            if terminal_state.buffer.row.is_empty() {
+                // This is to prevent out of bounds error when we create a new file and try to append text to it.
+                terminal_state.editor_append_row("");
             }
         }
-        'j' => {
-            if terminal_state.numrows > 0 && terminal_state.cy < terminal_state.numrows as usize - 1
-            {
-                // The -1 is required as the dimensions are 0 indexed.
-                let next_line = terminal_state.row[terminal_state.cy as usize + 1].rsize;
-                terminal_state.cy = terminal_state.cy + 1;
-                if terminal_state.cx > next_line {
-                    terminal_state.cx = next_line;
-                }
-            }
-            terminal_state.move_cursor();
+        Action::DeleteChar => {
+            terminal_state.editor_operate_motion(Operator::Delete, Motion::Right, count, register)
         }
-        'h' => {
-            if terminal_state.cx > 0 {
-                terminal_state.cx = terminal_state.cx - 1;
-            }
-            terminal_state.move_cursor();
+        Action::DeleteToEnd => {
+            terminal_state.editor_operate_motion(Operator::Delete, Motion::LineEnd, count, register)
         }
-        'k' => {
-            if terminal_state.cy > 0 {
-                let prev_line = terminal_state.row[terminal_state.cy as usize - 1].rsize;
-                terminal_state.cy -= 1;
-                if terminal_state.cx > prev_line {
-                    terminal_state.cx = prev_line;
-                }
-            }
-            terminal_state.move_cursor();
+        Action::ChangeToEnd => {
+            terminal_state.editor_operate_motion(Operator::Change, Motion::LineEnd, count, register)
+        }
+        Action::SearchForward => terminal_state.editor_start_search(Direction::Forward),
+        Action::SearchBackward => terminal_state.editor_start_search(Direction::Backward),
+        Action::SearchNext => terminal_state.editor_search_next(false, count.unwrap_or(1)),
+        Action::SearchPrevious => terminal_state.editor_search_next(true, count.unwrap_or(1)),
+        Action::SearchWordForward => {
+            terminal_state.editor_search_word(Direction::Forward, count.unwrap_or(1))
         }
-        'l' => {
-            let line = terminal_state.row[terminal_state.cy as usize].rsize;
-            if terminal_state.cx <= line {
-                terminal_state.cx += 1;
+        Action::SearchWordBackward => {
+            terminal_state.editor_search_word(Direction::Backward, count.unwrap_or(1))
+        }
+        Action::CommandLine => terminal_state.editor_command_line(),
+        Action::Visual => terminal_state.editor_toggle_visual(EditorMode::VISUAL),
+        Action::VisualLine => terminal_state.editor_toggle_visual(EditorMode::VISUALLINE),
+        Action::VisualBlock => terminal_state.editor_toggle_visual(EditorMode::VISUALBLOCK),
+        Action::PutAfter | Action::PutBefore => {
+            for _ in 0..count.unwrap_or(1) {
+                terminal_state.editor_put(action == Action::PutBefore, register);
             }
-            terminal_state.move_cursor();
         }
-        _ => {}
+        Action::NextTab => terminal_state.editor_next_tab(count),
+        Action::PreviousTab => terminal_state.editor_previous_tab(count.unwrap_or(1)),
+        Action::Window(key) => terminal_state.editor_window_command(key, count),
+        Action::ExpandSelection => terminal_state.editor_expand_selection(),
+        Action::ShrinkSelection => terminal_state.editor_shrink_selection(),
+        Action::NextItem => terminal_state.editor_goto_item(true, count.unwrap_or(1)),
+        Action::PreviousItem => terminal_state.editor_goto_item(false, count.unwrap_or(1)),
+    }
+
+    Ok(false)
+}
+
+pub fn visual_mode_shortcuts(terminal_state: &mut EditorState, key: char) {
+    if count_or_motion(terminal_state, key) {
+        return;
+    }
+    let register = terminal_state.pending.register.take();
+    terminal_state.pending = PendingCommand::default();
+
+    let operator = match key {
+        'd' | 'x' => Operator::Delete,
+        'y' => Operator::Yank,
+        'c' | 's' => Operator::Change,
+        '>' => Operator::Indent,
+        '<' => Operator::Outdent,
+        '~' => Operator::ToggleCase,
+        'u' => Operator::Lowercase,
+        'U' => Operator::Uppercase,
+        'v' => return terminal_state.editor_toggle_visual(EditorMode::VISUAL),
+        'V' => return terminal_state.editor_toggle_visual(EditorMode::VISUALLINE),
+        ':' => return terminal_state.editor_command_line(),
+        'o' => {
+            let cursor = (terminal_state.cx, terminal_state.cy);
+            (terminal_state.cx, terminal_state.cy) = terminal_state.anchor;
+            terminal_state.anchor = cursor;
+            return;
+        }
+        _ => return,
+    };
+
+    if let Some(selection) = terminal_state.editor_selection() {
+        terminal_state.editor_apply_operator(operator, selection, register);
     }
 }
@@ -0,0 +1,581 @@
+use std::io;
+use std::time::{Duration, Instant};
+use std::{fmt, mem};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::config::KeymapConfig;
+use crate::editor::{EditorMode, EditorState};
+use crate::keyboard::run_action;
+
+/// Mappings expanded while resolving one key before giving up on what is
+/// most likely a mapping that maps to itself.
+const MAX_EXPANSIONS: usize = 1000;
+
+/// Something a key sequence can be mapped to by name, e.g. `save` in
+/// `"<C-s>" = "save"`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Quit,
+    Save,
+    Undo,
+    Redo,
+    InsertMode,
+    CommandLine,
+    SearchForward,
+    SearchBackward,
+    SearchNext,
+    SearchPrevious,
+    SearchWordForward,
+    SearchWordBackward,
+    Visual,
+    VisualLine,
+    VisualBlock,
+    PutAfter,
+    PutBefore,
+    DeleteChar,
+    DeleteToEnd,
+    ChangeToEnd,
+    NextTab,
+    PreviousTab,
+    ExpandSelection,
+    ShrinkSelection,
+    NextItem,
+    PreviousItem,
+    /// A Ctrl-W command, named by the key that follows Ctrl-W.
+    Window(char),
+}
+
+const ACTIONS: [(&str, Action); 41] = [
+    ("quit", Action::Quit),
+    ("save", Action::Save),
+    ("undo", Action::Undo),
+    ("redo", Action::Redo),
+    ("insert_mode", Action::InsertMode),
+    ("command_line", Action::CommandLine),
+    ("search_forward", Action::SearchForward),
+    ("search_backward", Action::SearchBackward),
+    ("search_next", Action::SearchNext),
+    ("search_previous", Action::SearchPrevious),
+    ("search_word_forward", Action::SearchWordForward),
+    ("search_word_backward", Action::SearchWordBackward),
+    ("visual", Action::Visual),
+    ("visual_line", Action::VisualLine),
+    ("visual_block", Action::VisualBlock),
+    ("put_after", Action::PutAfter),
+    ("put_before", Action::PutBefore),
+    ("delete_char", Action::DeleteChar),
+    ("delete_to_end", Action::DeleteToEnd),
+    ("change_to_end", Action::ChangeToEnd),
+    ("next_tab", Action::NextTab),
+    ("previous_tab", Action::PreviousTab),
+    ("expand_selection", Action::ExpandSelection),
+    ("shrink_selection", Action::ShrinkSelection),
+    ("next_item", Action::NextItem),
+    ("previous_item", Action::PreviousItem),
+    ("window_split", Action::Window('s')),
+    ("window_vsplit", Action::Window('v')),
+    ("window_next", Action::Window('w')),
+    ("window_previous", Action::Window('W')),
+    ("window_left", Action::Window('h')),
+    ("window_down", Action::Window('j')),
+    ("window_up", Action::Window('k')),
+    ("window_right", Action::Window('l')),
+    ("window_close", Action::Window('c')),
+    ("window_only", Action::Window('o')),
+    ("window_taller", Action::Window('+')),
+    ("window_shorter", Action::Window('-')),
+    ("window_wider", Action::Window('>')),
+    ("window_narrower", Action::Window('<')),
+    ("window_equalize", Action::Window('=')),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(action, _)| *action == name)
+            .map(|(_, action)| *action)
+    }
+
+    fn name(&self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|(_, action)| action == self)
+            .map_or("", |(name, _)| name)
+    }
+}
+
+/// Normal mode defaults, which used to be hardcoded.
+const NORMAL: [(&str, &str); 43] = [
+    ("<C-q>", "quit"),
+    ("<C-s>", "save"),
+    ("u", "undo"),
+    ("<C-r>", "redo"),
+    ("i", "insert_mode"),
+    (":", "command_line"),
+    ("/", "search_forward"),
+    ("?", "search_backward"),
+    ("n", "search_next"),
+    ("N", "search_previous"),
+    ("*", "search_word_forward"),
+    ("#", "search_word_backward"),
+    ("v", "visual"),
+    ("V", "visual_line"),
+    ("<C-v>", "visual_block"),
+    ("p", "put_after"),
+    ("P", "put_before"),
+    ("x", "delete_char"),
+    ("D", "delete_to_end"),
+    ("C", "change_to_end"),
+    ("gt", "next_tab"),
+    ("gT", "previous_tab"),
+    ("]m", "next_item"),
+    ("[m", "previous_item"),
+    ("<A-o>", "expand_selection"),
+    ("<C-w>s", "window_split"),
+    ("<C-w>S", "window_split"),
+    ("<C-w>v", "window_vsplit"),
+    ("<C-w>w", "window_next"),
+    ("<C-w><C-w>", "window_next"),
+    ("<C-w>W", "window_previous"),
+    ("<C-w>h", "window_left"),
+    ("<C-w>j", "window_down"),
+    ("<C-w>k", "window_up"),
+    ("<C-w>l", "window_right"),
+    ("<C-w>c", "window_close"),
+    ("<C-w>q", "window_close"),
+    ("<C-w>o", "window_only"),
+    ("<C-w>+", "window_taller"),
+    ("<C-w>-", "window_shorter"),
+    ("<C-w>>", "window_wider"),
+    ("<C-w><", "window_narrower"),
+    ("<C-w>=", "window_equalize"),
+];
+
+const INSERT: [(&str, &str); 2] = [("<C-q>", "quit"), ("<C-s>", "save")];
+
+const VISUAL: [(&str, &str); 7] = [
+    ("<C-q>", "quit"),
+    ("<C-s>", "save"),
+    ("<C-v>", "visual_block"),
+    ("]m", "next_item"),
+    ("[m", "previous_item"),
+    ("<A-o>", "expand_selection"),
+    ("<A-i>", "shrink_selection"),
+];
+
+/// What a key sequence is mapped to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    Action(Action),
+    /// Other keys, which are mapped again themselves when `remap` is set.
+    Keys {
+        keys: Vec<KeyEvent>,
+        remap: bool,
+    },
+}
+
+impl Binding {
+    /// `rhs` names an action when it is one, and is a key sequence otherwise.
+    pub fn parse(rhs: &str, remap: bool) -> Result<Self, String> {
+        match Action::from_name(rhs) {
+            Some(action) => Ok(Binding::Action(action)),
+            None => Ok(Binding::Keys {
+                keys: parse_keys(rhs)?,
+                remap,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Action(action) => write!(f, "{}", action.name()),
+            Binding::Keys { keys, remap } => {
+                let star = if *remap { "" } else { "*" };
+                write!(f, "{star}{}", keys_to_string(keys))
+            }
+        }
+    }
+}
+
+/// Modes a mapping can be made for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapMode {
+    Normal,
+    Insert,
+    /// All three visual modes.
+    Visual,
+}
+
+impl MapMode {
+    fn letter(self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Insert => 'i',
+            MapMode::Visual => 'v',
+        }
+    }
+}
+
+/// Strips what does not tell keys apart, so that a typed `W` equals the `W`
+/// of a mapping whether or not Shift was reported.
+pub fn normalize(key: KeyEvent) -> KeyEvent {
+    let mut modifiers =
+        key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+    if let KeyCode::Char(_) = key.code {
+        modifiers.remove(KeyModifiers::SHIFT);
+    }
+    KeyEvent::new(key.code, modifiers)
+}
+
+/// Parses vim key notation: plain chars, and names such as `<Esc>`, `<CR>`,
+/// `<Space>`, `<lt>` or `<C-w>` in angle brackets.
+pub fn parse_keys(text: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let special = rest
+            .strip_prefix('<')
+            .and_then(|tail| tail.find('>').map(|end| &tail[..end]))
+            .filter(|name| !name.is_empty());
+        let Some(name) = special else {
+            keys.push(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+
+        keys.push(parse_special(name).ok_or_else(|| format!("Unknown key: <{name}>"))?);
+        rest = &rest[name.len() + 2..];
+    }
+
+    if keys.is_empty() {
+        return Err(String::from("Argument required"));
+    }
+    Ok(keys)
+}
+
+/// Parses the inside of `<...>`.
+fn parse_special(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut base = name;
+    // `<C-->` is Ctrl and `-`, so only split on dashes followed by something.
+    while let Some((prefix, tail)) = base.split_once('-').filter(|(_, tail)| !tail.is_empty()) {
+        modifiers |= match prefix.to_ascii_uppercase().as_str() {
+            "C" => KeyModifiers::CONTROL,
+            "A" | "M" => KeyModifiers::ALT,
+            "S" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+        base = tail;
+    }
+
+    let mut chars = base.chars();
+    let code = match (chars.next(), chars.next()) {
+        // Ctrl combinations are reported with the lowercase letter.
+        (Some(c), None) if modifiers.contains(KeyModifiers::CONTROL) => {
+            KeyCode::Char(c.to_ascii_lowercase())
+        }
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match base.to_ascii_lowercase().as_str() {
+            "esc" => KeyCode::Esc,
+            "cr" | "enter" | "return" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "bs" => KeyCode::Backspace,
+            "del" => KeyCode::Delete,
+            "space" => KeyCode::Char(' '),
+            "lt" => KeyCode::Char('<'),
+            "bar" => KeyCode::Char('|'),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            f => KeyCode::F(f.strip_prefix('f')?.parse().ok()?),
+        },
+    };
+
+    Some(normalize(KeyEvent::new(code, modifiers)))
+}
+
+fn key_to_string(key: &KeyEvent) -> String {
+    let name = match key.code {
+        KeyCode::Char('<') => String::from("lt"),
+        KeyCode::Char(' ') => String::from("Space"),
+        KeyCode::Char(c) if key.modifiers.is_empty() => return c.to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Esc => String::from("Esc"),
+        KeyCode::Enter => String::from("CR"),
+        KeyCode::Tab => String::from("Tab"),
+        KeyCode::Backspace => String::from("BS"),
+        KeyCode::Delete => String::from("Del"),
+        KeyCode::F(n) => format!("F{n}"),
+        code => format!("{code:?}"),
+    };
+
+    let mut prefix = String::new();
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        prefix.push_str("C-");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        prefix.push_str("A-");
+    }
+    if key.modifiers.contains(KeyModifiers::SHIFT) {
+        prefix.push_str("S-");
+    }
+    format!("<{prefix}{name}>")
+}
+
+pub fn keys_to_string(keys: &[KeyEvent]) -> String {
+    keys.iter().map(key_to_string).collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Mapping {
+    keys: Vec<KeyEvent>,
+    binding: Binding,
+}
+
+/// The key sequences mapped in one mode. The built-in mappings are kept
+/// apart from the user's, which take precedence, so that keys from a
+/// `noremap` still get their built-in meaning.
+#[derive(Debug, Default)]
+pub struct Keymap {
+    defaults: Vec<Mapping>,
+    user: Vec<Mapping>,
+}
+
+impl Keymap {
+    fn with_defaults(defaults: &[(&str, &str)]) -> Self {
+        let mut keymap = Self::default();
+        for (lhs, rhs) in defaults {
+            keymap.defaults.push(Mapping {
+                keys: parse_keys(lhs).unwrap(),
+                binding: Binding::parse(rhs, false).unwrap(),
+            });
+        }
+        keymap
+    }
+
+    /// Maps `keys` to `binding`, replacing what they were mapped to before.
+    pub fn map(&mut self, keys: Vec<KeyEvent>, binding: Binding) {
+        self.user.retain(|mapping| mapping.keys != keys);
+        self.user.push(Mapping { keys, binding });
+    }
+
+    /// What `typed` is mapped to, and whether it is the start of a longer
+    /// mapping too. User mappings are only looked at when `remap` is set.
+    pub fn lookup(&self, typed: &[KeyEvent], remap: bool) -> (Option<&Binding>, bool) {
+        let user: &[Mapping] = if remap { &self.user } else { &[] };
+        let mappings = || user.iter().chain(&self.defaults);
+        let exact = mappings()
+            .find(|mapping| mapping.keys == typed)
+            .map(|mapping| &mapping.binding);
+        let longer = mappings()
+            .any(|mapping| mapping.keys.len() > typed.len() && mapping.keys.starts_with(typed));
+
+        (exact, longer)
+    }
+}
+
+/// One keymap per mode.
+#[derive(Debug)]
+pub struct Keymaps {
+    pub normal: Keymap,
+    pub insert: Keymap,
+    pub visual: Keymap,
+}
+
+impl Keymaps {
+    pub fn new() -> Self {
+        Self {
+            normal: Keymap::with_defaults(&NORMAL),
+            insert: Keymap::with_defaults(&INSERT),
+            visual: Keymap::with_defaults(&VISUAL),
+        }
+    }
+
+    pub fn get(&self, mode: MapMode) -> &Keymap {
+        match mode {
+            MapMode::Normal => &self.normal,
+            MapMode::Insert => &self.insert,
+            MapMode::Visual => &self.visual,
+        }
+    }
+
+    pub fn get_mut(&mut self, mode: MapMode) -> &mut Keymap {
+        match mode {
+            MapMode::Normal => &mut self.normal,
+            MapMode::Insert => &mut self.insert,
+            MapMode::Visual => &mut self.visual,
+        }
+    }
+
+    /// Adds the mappings of the config file, which are not remapped.
+    pub fn apply(&mut self, config: &KeymapConfig) -> Result<(), String> {
+        let tables = [
+            (MapMode::Normal, &config.normal),
+            (MapMode::Insert, &config.insert),
+            (MapMode::Visual, &config.visual),
+        ];
+        for (mode, table) in tables {
+            for (lhs, rhs) in table {
+                let keys = parse_keys(lhs)?;
+                let binding = Binding::parse(rhs, false)?;
+                self.get_mut(mode).map(keys, binding);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the mappings of `modes` starting with `prefix`, for `:map`.
+    pub fn list(&self, modes: &[MapMode], prefix: &[KeyEvent]) -> String {
+        let mut lines = Vec::new();
+        for mode in modes {
+            for mapping in &self.get(*mode).user {
+                if mapping.keys.starts_with(prefix) {
+                    lines.push(format!(
+                        "{} {} {}",
+                        mode.letter(),
+                        keys_to_string(&mapping.keys),
+                        mapping.binding
+                    ));
+                }
+            }
+        }
+
+        if lines.is_empty() {
+            String::from("No mapping found")
+        } else {
+            lines.join(", ")
+        }
+    }
+}
+
+impl EditorState {
+    /// Keymap for the current mode, or `None` when keys go straight to the
+    /// prompt, a substitution being confirmed or a half typed command.
+    fn editor_map_mode(&self) -> Option<MapMode> {
+        if self.substitute.is_some() || self.pending.is_partial() {
+            return None;
+        }
+        match self.mode {
+            EditorMode::NORMAL => Some(MapMode::Normal),
+            EditorMode::INSERT => Some(MapMode::Insert),
+            ref mode if mode.is_visual() => Some(MapMode::Visual),
+            _ => None,
+        }
+    }
+
+    /// Takes a key press. Returns true when the editor should quit.
+    pub fn editor_feed_key(&mut self, key: KeyEvent) -> io::Result<bool> {
+        self.typed.push((normalize(key), true));
+        self.typed_at = Instant::now();
+        self.editor_resolve_keys(false)
+    }
+
+    /// Whether the keys typed so far have waited long enough for the rest of
+    /// a longer mapping.
+    pub fn editor_keys_timed_out(&self) -> bool {
+        !self.typed.is_empty()
+            && self.typed_at.elapsed() >= Duration::from_millis(self.config.timeoutlen)
+    }
+
+    /// Runs the keys typed so far, unless they could still become a longer
+    /// mapping and `timeout` is not set. Returns true when the editor should
+    /// quit.
+    pub fn editor_resolve_keys(&mut self, timeout: bool) -> io::Result<bool> {
+        let mut expansions = 0;
+        while !self.typed.is_empty() {
+            let Some(mode) = self.editor_map_mode() else {
+                let (key, _) = self.typed.remove(0);
+                if self.editor_handle_key(key)? {
+                    self.typed.clear();
+                    return Ok(true);
+                }
+                continue;
+            };
+
+            let keys: Vec<KeyEvent> = self.typed.iter().map(|(key, _)| *key).collect();
+            let keymap = self.keymaps.get(mode);
+            let remap = |len: usize| self.typed[..len].iter().all(|(_, remap)| *remap);
+            let (_, longer) = keymap.lookup(&keys, remap(keys.len()));
+            if longer && !timeout {
+                return Ok(false);
+            }
+
+            // The longest run of typed keys that is mapped to something.
+            let found = (1..=keys.len()).rev().find_map(|len| {
+                let (exact, _) = keymap.lookup(&keys[..len], remap(len));
+                exact.map(|binding| (len, binding.clone()))
+            });
+            let Some((len, binding)) = found else {
+                let (key, _) = self.typed.remove(0);
+                if self.editor_handle_key(key)? {
+                    self.typed.clear();
+                    return Ok(true);
+                }
+                continue;
+            };
+
+            let rest = self.typed.split_off(len);
+            self.typed = rest;
+            match binding {
+                Binding::Action(action) => {
+                    if run_action(self, action)? {
+                        self.typed.clear();
+                        return Ok(true);
+                    }
+                }
+                Binding::Keys { keys, remap } => {
+                    expansions += 1;
+                    if expansions > MAX_EXPANSIONS {
+                        self.typed.clear();
+                        self.message = Some(String::from("Recursive mapping"));
+                        return Ok(false);
+                    }
+                    let rest = mem::take(&mut self.typed);
+                    self.typed = keys.into_iter().map(|key| (key, remap)).collect();
+                    self.typed.extend(rest);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Runs `:map` and friends for `modes`.
+    pub fn editor_map(&mut self, modes: &[MapMode], remap: bool, args: Option<&str>) {
+        let Some(args) = args else {
+            self.message = Some(self.keymaps.list(modes, &[]));
+            return;
+        };
+        let (lhs, rhs) = match args.split_once(char::is_whitespace) {
+            Some((lhs, rhs)) => (lhs, Some(rhs.trim())),
+            None => (args, None),
+        };
+
+        let result = parse_keys(lhs).and_then(|keys| match rhs {
+            None => Ok(Some(self.keymaps.list(modes, &keys))),
+            Some(rhs) => {
+                let binding = Binding::parse(rhs, remap)?;
+                for mode in modes {
+                    self.keymaps
+                        .get_mut(*mode)
+                        .map(keys.clone(), binding.clone());
+                }
+                Ok(None)
+            }
+        });
+        match result {
+            Ok(msg) => self.message = msg,
+            Err(msg) => self.message = Some(msg),
+        }
+    }
+}
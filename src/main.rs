@@ -1,40 +1,65 @@
 use std::{env, io};
 
+mod buffer;
+mod clipboard;
+mod command;
+mod config;
 mod editor;
 mod keyboard;
+mod keymap;
+mod motion;
+mod mouse;
+mod operator;
+mod prompt;
+mod registers;
+mod screen;
+mod script;
+mod search;
+mod selection;
+mod substitute;
+mod syntax;
+mod tab;
 mod terminal;
+mod textbuffer;
+mod theme;
+mod treesitter;
+mod undo;
+mod window;
+mod wrap;
 
 use editor::*;
 
 const VERSION: &str = "0.0.1";
-const TABSTOP: usize = 4;
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
     let mut term = EditorState::new();
-    term.dimensions.rows -= 1;
     crossterm::terminal::enable_raw_mode()?;
-    term.refresh_screen();
-    if args.len() == 2 {
-        term.editor_open(&args[1]);
+    crossterm::execute!(
+        io::stdout(),
+        crossterm::event::EnableMouseCapture,
+        crossterm::event::EnableBracketedPaste
+    )?;
+    for file in &args[1..] {
+        term.editor_open(file);
     }
+    // Start on the first file, which took over buffer 1.
+    term.editor_switch_buffer(1);
 
     loop {
-        term.refresh_screen();
         term.change_cursor()?;
-        term.editor_status_line()?;
-
-        crossterm::execute!(
-            io::stdout(),
-            crossterm::cursor::MoveTo(term.rx as u16 - term.coloff, term.cy as u16 - term.rowoff),
-        )
-        .unwrap();
+        term.refresh_screen()?;
 
         if term.process_char()? {
             break;
         }
     }
 
+    crossterm::execute!(
+        io::stdout(),
+        crossterm::event::DisableBracketedPaste,
+        crossterm::event::DisableMouseCapture
+    )?;
     crossterm::terminal::disable_raw_mode()?;
     clear()?;
     Ok(())
@@ -0,0 +1,218 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::textbuffer::TextBuffer;
+use crate::undo::Cursor;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// `gk` and `gj`, which move by screen lines when rows wrap.
+    ScreenUp,
+    ScreenDown,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FirstLine,
+    LastLine,
+}
+
+impl Motion {
+    pub fn from_key(key: char) -> Option<Motion> {
+        let motion = match key {
+            'h' => Motion::Left,
+            'l' | ' ' => Motion::Right,
+            'k' => Motion::Up,
+            'j' => Motion::Down,
+            'w' => Motion::WordForward,
+            'b' => Motion::WordBackward,
+            'e' => Motion::WordEnd,
+            '0' => Motion::LineStart,
+            '_' | '^' => Motion::FirstNonBlank,
+            '$' => Motion::LineEnd,
+            'G' => Motion::LastLine,
+            _ => return None,
+        };
+
+        Some(motion)
+    }
+
+    /// Operators over a linewise motion act on whole rows.
+    pub fn linewise(&self) -> bool {
+        matches!(
+            self,
+            Motion::Up | Motion::Down | Motion::FirstLine | Motion::LastLine
+        )
+    }
+
+    /// Whether the char under the target position is included when an
+    /// operator is applied over a charwise motion.
+    pub fn inclusive(&self) -> bool {
+        matches!(self, Motion::WordEnd | Motion::LineEnd)
+    }
+}
+
+#[derive(PartialEq)]
+enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// The first char of every grapheme cluster in row `cy`, so that indexes line
+/// up with columns.
+fn line_chars(buffer: &TextBuffer, cy: usize) -> Vec<char> {
+    buffer
+        .line(cy)
+        .graphemes(true)
+        .filter_map(|g| g.chars().next())
+        .collect()
+}
+
+fn word_forward(buffer: &TextBuffer, (mut x, mut y): Cursor) -> Cursor {
+    let mut chars = line_chars(buffer, y);
+    if x < chars.len() && class(chars[x]) != CharClass::Blank {
+        let start = class(chars[x]);
+        while x < chars.len() && class(chars[x]) == start {
+            x += 1;
+        }
+    }
+
+    loop {
+        while x < chars.len() && class(chars[x]) == CharClass::Blank {
+            x += 1;
+        }
+        if x < chars.len() || y + 1 >= buffer.len() {
+            return (x, y);
+        }
+
+        y += 1;
+        x = 0;
+        chars = line_chars(buffer, y);
+        // An empty line counts as a word of its own.
+        if chars.is_empty() {
+            return (0, y);
+        }
+    }
+}
+
+fn word_backward(buffer: &TextBuffer, (x, mut y): Cursor) -> Cursor {
+    let mut chars = line_chars(buffer, y);
+    let mut x = x.min(chars.len());
+
+    loop {
+        if x == 0 {
+            if y == 0 {
+                return (0, 0);
+            }
+            y -= 1;
+            chars = line_chars(buffer, y);
+            x = chars.len();
+            if chars.is_empty() {
+                return (0, y);
+            }
+        } else if class(chars[x - 1]) == CharClass::Blank {
+            x -= 1;
+        } else {
+            break;
+        }
+    }
+
+    let start = class(chars[x - 1]);
+    while x > 0 && class(chars[x - 1]) == start {
+        x -= 1;
+    }
+
+    (x, y)
+}
+
+fn word_end(buffer: &TextBuffer, (x, mut y): Cursor) -> Cursor {
+    let mut chars = line_chars(buffer, y);
+    let mut x = x + 1;
+
+    loop {
+        if x >= chars.len() {
+            if y + 1 >= buffer.len() {
+                return (chars.len().saturating_sub(1), y);
+            }
+            y += 1;
+            x = 0;
+            chars = line_chars(buffer, y);
+        } else if class(chars[x]) == CharClass::Blank {
+            x += 1;
+        } else {
+            break;
+        }
+    }
+
+    let start = class(chars[x]);
+    while x + 1 < chars.len() && class(chars[x + 1]) == start {
+        x += 1;
+    }
+
+    (x, y)
+}
+
+fn first_non_blank(buffer: &TextBuffer, cy: usize) -> usize {
+    line_chars(buffer, cy)
+        .into_iter()
+        .take_while(|c| c.is_whitespace())
+        .count()
+}
+
+/// Position the cursor ends up at after moving by `motion` from `cursor`.
+/// `count` is the number typed before the motion, if any.
+pub fn target(buffer: &TextBuffer, cursor: Cursor, motion: Motion, count: Option<usize>) -> Cursor {
+    if buffer.is_empty() {
+        return cursor;
+    }
+
+    let n = count.unwrap_or(1).max(1);
+    let last = buffer.len() - 1;
+    let (cx, cy) = cursor;
+
+    match motion {
+        Motion::Left => (cx.saturating_sub(n), cy),
+        Motion::Right => ((cx + n).min(buffer.line_len(cy)), cy),
+        Motion::Up | Motion::ScreenUp => {
+            let cy = cy.saturating_sub(n);
+            (cx.min(buffer.line_len(cy)), cy)
+        }
+        Motion::Down | Motion::ScreenDown => {
+            let cy = (cy + n).min(last);
+            (cx.min(buffer.line_len(cy)), cy)
+        }
+        Motion::WordForward => (0..n).fold(cursor, |pos, _| word_forward(buffer, pos)),
+        Motion::WordBackward => (0..n).fold(cursor, |pos, _| word_backward(buffer, pos)),
+        Motion::WordEnd => (0..n).fold(cursor, |pos, _| word_end(buffer, pos)),
+        Motion::LineStart => (0, cy),
+        Motion::FirstNonBlank => (first_non_blank(buffer, cy), cy),
+        Motion::LineEnd => {
+            let cy = (cy + n - 1).min(last);
+            (buffer.line_len(cy).saturating_sub(1), cy)
+        }
+        Motion::FirstLine => {
+            let cy = count.unwrap_or(1).saturating_sub(1).min(last);
+            (first_non_blank(buffer, cy), cy)
+        }
+        Motion::LastLine => {
+            let cy = count.map_or(last, |n| n.saturating_sub(1).min(last));
+            (first_non_blank(buffer, cy), cy)
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crossterm::event::{MouseButton, MouseEvent, MouseEventKind};
+
+use crate::editor::{EditorMode, EditorState, Erow};
+use crate::undo::Cursor;
+
+/// Rows scrolled by one notch of the mouse wheel.
+const SCROLL_LINES: usize = 3;
+
+impl EditorState {
+    /// Buffer position drawn at the screen cell `(column, row)`, or `None`
+    /// when the cell is not part of the text area of the current window.
+    fn editor_screen_to_cursor(&self, column: u16, row: u16) -> Option<Cursor> {
+        let text = self.editor_text_area();
+        if !text.contains(column, row) || self.buffer.row.is_empty() {
+            return None;
+        }
+
+        let (column, line) = ((column - text.x) as usize, (row - text.y) as usize);
+        if self.config.wrap {
+            return Some(self.editor_wrapped_cell_to_cursor(column, line));
+        }
+        let cy = (line + self.rowoff).min(self.buffer.row.len() - 1);
+        let erow = Erow::from(&self.buffer.row.line(cy), self.config.tabstop);
+        let cx = erow.editor_row_rx_to_cx(column + self.coloff);
+
+        Some((cx, cy))
+    }
+
+    pub fn editor_mouse(&mut self, event: MouseEvent) {
+        if matches!(self.mode, EditorMode::COMMAND | EditorMode::SEARCH) {
+            return;
+        }
+
+        // Clicking or scrolling a window makes it the current one.
+        if matches!(
+            event.kind,
+            MouseEventKind::Down(_) | MouseEventKind::ScrollDown | MouseEventKind::ScrollUp
+        ) {
+            if let Some(id) = self.editor_window_at(event.column, event.row) {
+                if id != self.window && self.mode.is_visual() {
+                    self.mode = EditorMode::NORMAL;
+                }
+                self.editor_focus_window(id);
+            }
+        }
+
+        let rows = (self.editor_text_area().height as usize).max(1);
+        let scrolloff = self.config.scrolloff.min((rows - 1) / 2);
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                if let Some(cursor) = self.editor_screen_to_cursor(event.column, event.row) {
+                    if self.mode.is_visual() {
+                        self.mode = EditorMode::NORMAL;
+                    }
+                    (self.cx, self.cy) = cursor;
+                }
+            }
+            MouseEventKind::Drag(MouseButton::Left) => {
+                if let Some(cursor) = self.editor_screen_to_cursor(event.column, event.row) {
+                    // Dragging starts a selection at the cell first clicked.
+                    if !self.mode.is_visual() {
+                        self.editor_toggle_visual(EditorMode::VISUAL);
+                    }
+                    (self.cx, self.cy) = cursor;
+                }
+            }
+            MouseEventKind::ScrollDown => {
+                let last = self.buffer.row.len().saturating_sub(1);
+                self.rowoff = (self.rowoff + SCROLL_LINES).min(last);
+                if self.cy < self.rowoff + scrolloff && !self.buffer.row.is_empty() {
+                    self.cy = (self.rowoff + scrolloff).min(last);
+                    self.cx = self.cx.min(self.buffer.row.line_len(self.cy));
+                }
+            }
+            MouseEventKind::ScrollUp => {
+                self.rowoff = self.rowoff.saturating_sub(SCROLL_LINES);
+                // Wrapped rows can take several lines, so count the lines
+                // the way the view does.
+                let bottom = if self.config.wrap {
+                    self.editor_wrapped_last_row(rows - scrolloff)
+                } else {
+                    self.rowoff + rows - 1 - scrolloff
+                };
+                if self.cy > bottom && !self.buffer.row.is_empty() {
+                    self.cy = bottom;
+                    self.cx = self.cx.min(self.buffer.row.line_len(self.cy));
+                }
+            }
+            _ => {}
+        }
+    }
+}
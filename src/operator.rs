@@ -0,0 +1,351 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::editor::{EditorMode, EditorState};
+use crate::motion::Motion;
+use crate::registers::Register;
+use crate::selection::{Selection, SelectionKind};
+use crate::textbuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Delete,
+    Yank,
+    Change,
+    Indent,
+    Outdent,
+    ToggleCase,
+    Uppercase,
+    Lowercase,
+}
+
+impl EditorState {
+    /// End of a charwise selection as an exclusive `(line, col)` position.
+    fn charwise_end(&self, selection: &Selection) -> (usize, usize) {
+        let (ex, ey) = selection.end;
+        if ex >= self.buffer.row.line_len(ey) {
+            (ey + 1, 0)
+        } else {
+            (ey, ex + 1)
+        }
+    }
+
+    pub fn editor_selection_text(&self, selection: &Selection) -> String {
+        let (first, last) = selection.rows();
+        match selection.kind {
+            SelectionKind::Linewise => self.buffer.row.text_between((first, 0), (last + 1, 0)),
+            SelectionKind::Charwise => {
+                let (sx, sy) = selection.start;
+                self.buffer
+                    .row
+                    .text_between((sy, sx), self.charwise_end(selection))
+            }
+            SelectionKind::Blockwise => (first..=last)
+                .map(|cy| {
+                    let (start, end) = selection.span(cy, self.buffer.row.line_len(cy)).unwrap();
+                    self.buffer.row.text_between((cy, start), (cy, end))
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    fn editor_delete_selection(&mut self, selection: &Selection) {
+        let (first, last) = selection.rows();
+        match selection.kind {
+            SelectionKind::Linewise => self.buffer.row.remove_lines(first, last + 1),
+            SelectionKind::Charwise => {
+                let (sx, sy) = selection.start;
+                let end = self.charwise_end(selection);
+                self.buffer.row.remove_between((sy, sx), end);
+            }
+            SelectionKind::Blockwise => {
+                for cy in (first..=last).rev() {
+                    let (start, end) = selection.span(cy, self.buffer.row.line_len(cy)).unwrap();
+                    self.buffer.row.remove_between((cy, start), (cy, end));
+                }
+            }
+        }
+        self.buffer.dirty = true;
+    }
+
+    /// Rewrites every selected char with `f`.
+    fn editor_map_selection(&mut self, selection: &Selection, f: impl Fn(char) -> String) {
+        let (first, last) = selection.rows();
+        for cy in first..=last {
+            let line_len = self.buffer.row.line_len(cy);
+            let (start, end) = selection.span(cy, line_len).unwrap();
+            let end = end.min(line_len);
+            let text = self.buffer.row.text_between((cy, start), (cy, end));
+            let mapped: String = text.chars().map(&f).collect();
+            if mapped != text {
+                self.buffer.row.remove_between((cy, start), (cy, end));
+                self.buffer.row.insert_str(cy, start, &mapped);
+                self.buffer.dirty = true;
+            }
+        }
+    }
+
+    fn editor_shift_lines(&mut self, first: usize, last: usize, indent: bool) {
+        for cy in first..=last {
+            let line = self.buffer.row.line(cy);
+            if indent {
+                if !line.is_empty() {
+                    let indent = self.editor_indent_unit();
+                    self.buffer.row.insert_str(cy, 0, &indent);
+                }
+            } else if line.starts_with('\t') {
+                self.buffer.row.delete_char(cy, 0);
+            } else {
+                let spaces = line.chars().take_while(|c| *c == ' ').count();
+                self.buffer
+                    .row
+                    .remove_between((cy, 0), (cy, spaces.min(self.config.tabstop)));
+            }
+        }
+        self.buffer.dirty = true;
+    }
+
+    /// Applies `op` over `selection`. Yanked and deleted text goes to
+    /// `register`, or to the unnamed and numbered registers when it is `None`.
+    pub fn editor_apply_operator(
+        &mut self,
+        op: Operator,
+        selection: Selection,
+        register: Option<char>,
+    ) {
+        let (first, last) = selection.rows();
+        self.mode = EditorMode::NORMAL;
+        (self.cx, self.cy) = selection.start;
+
+        match op {
+            Operator::Yank => {
+                let yanked = Register {
+                    text: self.editor_selection_text(&selection),
+                    kind: selection.kind,
+                };
+                if let Err(msg) = self.registers.store(register, yanked, false) {
+                    self.message = Some(msg);
+                } else if last - first + 1 > 2 {
+                    self.message = Some(format!("{} lines yanked", last - first + 1));
+                }
+            }
+            Operator::Delete | Operator::Change => {
+                let deleted = Register {
+                    text: self.editor_selection_text(&selection),
+                    kind: selection.kind,
+                };
+                if let Err(msg) = self.registers.store(register, deleted, true) {
+                    self.message = Some(msg);
+                    return;
+                }
+                self.editor_delete_selection(&selection);
+
+                if op == Operator::Change {
+                    if selection.kind == SelectionKind::Linewise {
+                        self.buffer.row.insert_line(first, "");
+                        self.cx = 0;
+                    }
+                    self.mode = EditorMode::INSERT;
+                } else if selection.kind == SelectionKind::Linewise {
+                    self.cx = 0;
+                }
+            }
+            Operator::Indent | Operator::Outdent => {
+                self.editor_shift_lines(first, last, op == Operator::Indent);
+                self.cx = 0;
+            }
+            Operator::ToggleCase => self.editor_map_selection(&selection, |c| {
+                if c.is_uppercase() {
+                    c.to_lowercase().collect()
+                } else {
+                    c.to_uppercase().collect()
+                }
+            }),
+            Operator::Uppercase => {
+                self.editor_map_selection(&selection, |c| c.to_uppercase().collect())
+            }
+            Operator::Lowercase => {
+                self.editor_map_selection(&selection, |c| c.to_lowercase().collect())
+            }
+        }
+
+        if self.buffer.row.is_empty() {
+            (self.cx, self.cy) = (0, 0);
+        } else {
+            self.cy = self.cy.min(self.buffer.row.len() - 1);
+            self.cx = self.cx.min(self.buffer.row.line_len(self.cy));
+        }
+    }
+
+    /// Applies `op` over the text between the cursor and where `motion` would
+    /// move it, e.g. `d3w` or `c$`.
+    pub fn editor_operate_motion(
+        &mut self,
+        op: Operator,
+        motion: Motion,
+        count: Option<usize>,
+        register: Option<char>,
+    ) {
+        if self.buffer.row.is_empty() {
+            return;
+        }
+
+        let cursor = (self.cx, self.cy);
+        let mut motion = motion;
+        // Like vim, `cw` on a word only changes up to the end of that word.
+        if op == Operator::Change && motion == Motion::WordForward {
+            let on_blank = self
+                .buffer
+                .row
+                .line(self.cy)
+                .graphemes(true)
+                .nth(self.cx)
+                .and_then(|g| g.chars().next())
+                .is_none_or(char::is_whitespace);
+            if !on_blank {
+                motion = Motion::WordEnd;
+            }
+        }
+
+        let target = self.editor_motion_target(motion, count);
+        let selection = if motion.linewise() {
+            Selection::new(SelectionKind::Linewise, cursor, target)
+        } else if motion == Motion::LineEnd && self.buffer.row.line_len(target.1) == 0 {
+            // `$` never takes in the newline, so on an empty row it reaches
+            // nothing, and over several rows it ends at the newline before.
+            if target.1 == cursor.1 {
+                if op == Operator::Change {
+                    self.mode = EditorMode::INSERT;
+                }
+                return;
+            }
+            let cy = target.1 - 1;
+            let end = (self.buffer.row.line_len(cy), cy);
+            Selection::new(SelectionKind::Charwise, cursor, end)
+        } else if motion.inclusive() {
+            Selection::new(SelectionKind::Charwise, cursor, target)
+        } else {
+            let (start, end) = if (cursor.1, cursor.0) <= (target.1, target.0) {
+                (cursor, target)
+            } else {
+                (target, cursor)
+            };
+            if start == end {
+                return;
+            }
+
+            // Like vim, `w` over the last word of a row stops at the end of
+            // that row instead of taking the indent of the next one along.
+            let indent = |(x, y): (usize, usize)| {
+                self.buffer
+                    .row
+                    .line(y)
+                    .graphemes(true)
+                    .take(x)
+                    .all(|g| g.trim().is_empty())
+            };
+            let end = if motion == Motion::WordForward && end.1 > start.1 && indent(end) {
+                (0, end.1)
+            } else {
+                end
+            };
+
+            // Exclusive motions leave out the char at the end. An end in the
+            // first column of a later row stops at the end of the row before.
+            let end = if end.0 == 0 && end.1 > start.1 {
+                let cy = end.1 - 1;
+                (self.buffer.row.line_len(cy).saturating_sub(1), cy)
+            } else {
+                (end.0 - 1, end.1)
+            };
+            Selection {
+                kind: SelectionKind::Charwise,
+                start,
+                end,
+            }
+        };
+
+        self.editor_apply_operator(op, selection, register);
+    }
+
+    /// Applies `op` over `count` whole rows starting at the cursor, e.g. `dd`
+    /// or `3yy`.
+    pub fn editor_operate_lines(&mut self, op: Operator, count: usize, register: Option<char>) {
+        if self.buffer.row.is_empty() {
+            return;
+        }
+
+        let last = (self.cy + count.max(1) - 1).min(self.buffer.row.len() - 1);
+        let selection =
+            Selection::new(SelectionKind::Linewise, (self.cx, self.cy), (self.cx, last));
+        self.editor_apply_operator(op, selection, register);
+    }
+
+    /// Puts the contents of `register` after the cursor, or before it when
+    /// `before` is set. Linewise text goes on rows of its own.
+    pub fn editor_put(&mut self, before: bool, register: Option<char>) {
+        let filename = self.buffer.filename.as_deref();
+        let Some(content) = self.registers.get(register, filename) else {
+            let name = register.unwrap_or('"');
+            self.message = Some(format!("Nothing in register {name}"));
+            return;
+        };
+
+        self.buffer.dirty = true;
+        match content.kind {
+            SelectionKind::Linewise => {
+                let at = if before || self.buffer.row.is_empty() {
+                    self.cy
+                } else {
+                    self.cy + 1
+                };
+                self.buffer.row.insert_str(at, 0, &content.text);
+                self.cy = at;
+                self.cx = 0;
+            }
+            SelectionKind::Charwise => {
+                if self.buffer.row.is_empty() {
+                    self.editor_append_row("");
+                }
+                let line_len = self.buffer.row.line_len(self.cy);
+                let col = if before || line_len == 0 {
+                    self.cx
+                } else {
+                    (self.cx + 1).min(line_len)
+                };
+                self.buffer.row.insert_str(self.cy, col, &content.text);
+                self.cx = if content.text.contains('\n') {
+                    col
+                } else {
+                    col + textbuffer::grapheme_len(&content.text).saturating_sub(1)
+                };
+            }
+            SelectionKind::Blockwise => {
+                let line_len = if self.buffer.row.is_empty() {
+                    0
+                } else {
+                    self.buffer.row.line_len(self.cy)
+                };
+                let col = if before || line_len == 0 {
+                    self.cx
+                } else {
+                    (self.cx + 1).min(line_len)
+                };
+                for (i, text) in content.text.split('\n').enumerate() {
+                    let cy = self.cy + i;
+                    if cy >= self.buffer.row.len() {
+                        self.editor_append_row("");
+                    }
+                    let line_len = self.buffer.row.line_len(cy);
+                    if line_len < col {
+                        self.buffer
+                            .row
+                            .insert_str(cy, line_len, &" ".repeat(col - line_len));
+                    }
+                    self.buffer.row.insert_str(cy, col, text);
+                }
+                self.cx = col;
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use unicode_width::UnicodeWidthStr;
+
+use crate::textbuffer::{col_to_byte, grapheme_len};
+
+/// What the caller should do after a key was fed to a `Prompt`.
+#[derive(Debug, PartialEq)]
+pub enum PromptAction {
+    Edit,
+    Submit(String),
+    Cancel,
+}
+
+/// Single line input drawn in the status line, with a history that can be
+/// walked with the arrow keys.
+#[derive(Debug, Default)]
+pub struct Prompt {
+    pub input: String,
+    // Cursor position in grapheme clusters.
+    pub cursor: usize,
+    history: Vec<String>,
+    history_idx: Option<usize>,
+}
+
+impl Prompt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.input.clear();
+        self.cursor = 0;
+        self.history_idx = None;
+    }
+
+    fn byte_idx(&self, at: usize) -> usize {
+        col_to_byte(&self.input, at)
+    }
+
+    /// Display width of the input before the cursor.
+    pub fn width(&self) -> usize {
+        self.input[..self.byte_idx(self.cursor)].width()
+    }
+
+    /// Inserts `text` at the cursor.
+    pub fn insert(&mut self, text: &str) {
+        let idx = self.byte_idx(self.cursor);
+        self.input.insert_str(idx, text);
+        self.cursor += grapheme_len(text);
+    }
+
+    fn set_input(&mut self, input: String) {
+        self.cursor = grapheme_len(&input);
+        self.input = input;
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> PromptAction {
+        match key.code {
+            KeyCode::Esc => return PromptAction::Cancel,
+            KeyCode::Enter => {
+                let input = self.input.clone();
+                if !input.is_empty() && self.history.last() != Some(&input) {
+                    self.history.push(input.clone());
+                }
+                self.history_idx = None;
+                return PromptAction::Submit(input);
+            }
+            KeyCode::Backspace => {
+                // Like vim, backspacing over an empty prompt leaves it.
+                if self.input.is_empty() {
+                    return PromptAction::Cancel;
+                }
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    let range = self.byte_idx(self.cursor)..self.byte_idx(self.cursor + 1);
+                    self.input.replace_range(range, "");
+                }
+            }
+            KeyCode::Delete if self.cursor < grapheme_len(&self.input) => {
+                let range = self.byte_idx(self.cursor)..self.byte_idx(self.cursor + 1);
+                self.input.replace_range(range, "");
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(grapheme_len(&self.input)),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = grapheme_len(&self.input),
+            KeyCode::Up => {
+                let idx = match self.history_idx {
+                    Some(0) => 0,
+                    Some(idx) => idx - 1,
+                    None if self.history.is_empty() => return PromptAction::Edit,
+                    None => self.history.len() - 1,
+                };
+                self.history_idx = Some(idx);
+                self.set_input(self.history[idx].clone());
+            }
+            KeyCode::Down => match self.history_idx {
+                Some(idx) if idx + 1 < self.history.len() => {
+                    self.history_idx = Some(idx + 1);
+                    self.set_input(self.history[idx + 1].clone());
+                }
+                Some(_) => {
+                    self.history_idx = None;
+                    self.set_input(String::new());
+                }
+                None => {}
+            },
+            KeyCode::Char(c) => {
+                let len = grapheme_len(&self.input);
+                let idx = self.byte_idx(self.cursor);
+                self.input.insert(idx, c);
+                self.cursor += grapheme_len(&self.input) - len;
+            }
+            _ => {}
+        }
+
+        PromptAction::Edit
+    }
+}
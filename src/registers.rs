@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use crate::clipboard::ClipboardProvider;
+use crate::selection::SelectionKind;
+
+/// Text held by a register, along with how it should be put back.
+#[derive(Debug, Clone)]
+pub struct Register {
+    pub text: String,
+    pub kind: SelectionKind,
+}
+
+impl Register {
+    fn charwise(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            kind: SelectionKind::Charwise,
+        }
+    }
+
+    /// Appends `other`, becoming linewise if either side is.
+    fn append(&mut self, other: Register) {
+        if self.kind == SelectionKind::Linewise || other.kind == SelectionKind::Linewise {
+            if !self.text.ends_with('\n') {
+                self.text.push('\n');
+            }
+            self.text.push_str(&other.text);
+            if !self.text.ends_with('\n') {
+                self.text.push('\n');
+            }
+            self.kind = SelectionKind::Linewise;
+        } else {
+            self.text.push_str(&other.text);
+        }
+    }
+}
+
+/// Vim style registers.
+///
+/// - `"` the unnamed register, holding whatever was last yanked or deleted
+/// - `a`-`z` named registers, `A`-`Z` append to them
+/// - `0` the last yank, `1`-`9` the history of deletes
+/// - `_` the black hole register, which discards what is written to it
+/// - `%` the current file name and `/` the last search, both read-only
+/// - `+` and `*` the system clipboard
+#[derive(Debug)]
+pub struct Registers {
+    unnamed: Option<Register>,
+    named: HashMap<char, Register>,
+    numbered: [Option<Register>; 10],
+    pub last_search: Option<String>,
+    pub clipboard: Box<dyn ClipboardProvider>,
+    // What was last sent to the clipboard, for providers that can not read
+    // it back.
+    clipboard_copy: Option<Register>,
+}
+
+pub fn is_valid_name(name: char) -> bool {
+    name.is_ascii_alphanumeric() || matches!(name, '"' | '_' | '%' | '/' | '+' | '*')
+}
+
+impl Registers {
+    pub fn new(clipboard: Box<dyn ClipboardProvider>) -> Self {
+        Self {
+            unnamed: None,
+            named: HashMap::new(),
+            numbered: Default::default(),
+            last_search: None,
+            clipboard,
+            clipboard_copy: None,
+        }
+    }
+
+    /// Stores `register` into `name`, or into the registers an unqualified
+    /// yank or delete goes to when `name` is `None`.
+    pub fn store(
+        &mut self,
+        name: Option<char>,
+        register: Register,
+        is_delete: bool,
+    ) -> Result<(), String> {
+        match name {
+            None | Some('"') => {
+                if is_delete {
+                    self.numbered[1..].rotate_right(1);
+                    self.numbered[1] = Some(register.clone());
+                } else {
+                    self.numbered[0] = Some(register.clone());
+                }
+                self.unnamed = Some(register);
+            }
+            Some('_') => {}
+            Some('+' | '*') => {
+                self.clipboard
+                    .copy(&register.text)
+                    .map_err(|e| format!("Clipboard ({}): {e}", self.clipboard.name()))?;
+                self.clipboard_copy = Some(register.clone());
+                self.unnamed = Some(register);
+            }
+            Some(c) if c.is_ascii_digit() => {
+                self.numbered[c.to_digit(10).unwrap() as usize] = Some(register.clone());
+                self.unnamed = Some(register);
+            }
+            Some(c) if c.is_ascii_lowercase() => {
+                self.named.insert(c, register.clone());
+                self.unnamed = Some(register);
+            }
+            Some(c) if c.is_ascii_uppercase() => {
+                let named = self
+                    .named
+                    .entry(c.to_ascii_lowercase())
+                    .and_modify(|named| named.append(register.clone()))
+                    .or_insert(register);
+                self.unnamed = Some(named.clone());
+            }
+            Some(c) => return Err(format!("Register {c} is read-only")),
+        }
+
+        Ok(())
+    }
+
+    /// Contents of register `name`, with the unnamed register for `None`.
+    pub fn get(&self, name: Option<char>, filename: Option<&str>) -> Option<Register> {
+        match name {
+            None | Some('"') => self.unnamed.clone(),
+            Some('%') => filename.map(Register::charwise),
+            Some('+' | '*') => match self.clipboard.paste() {
+                Ok(Some(text)) => Some(Register {
+                    kind: if text.ends_with('\n') {
+                        SelectionKind::Linewise
+                    } else {
+                        SelectionKind::Charwise
+                    },
+                    text,
+                }),
+                _ => self.clipboard_copy.clone(),
+            },
+            Some('/') => self.last_search.as_deref().map(Register::charwise),
+            Some(c) if c.is_ascii_digit() => {
+                self.numbered[c.to_digit(10).unwrap() as usize].clone()
+            }
+            Some(c) if c.is_ascii_alphabetic() => self.named.get(&c.to_ascii_lowercase()).cloned(),
+            _ => None,
+        }
+    }
+}
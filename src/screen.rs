@@ -0,0 +1,215 @@
+use std::io::{self, Write};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::style::{
+    Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor,
+};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::QueueableCommand;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// How a cell is drawn. `None` colors leave the terminal default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub reverse: bool,
+}
+
+impl Style {
+    pub fn new(fg: Color, bg: Color) -> Self {
+        Self {
+            fg: Some(fg),
+            bg: Some(bg),
+            reverse: false,
+        }
+    }
+
+    /// Colored text on the default background.
+    pub fn fg(fg: Color) -> Self {
+        Self {
+            fg: Some(fg),
+            ..Self::default()
+        }
+    }
+
+    /// `self`, with the colors it leaves unset taken from `base`.
+    pub fn or(self, base: Style) -> Self {
+        Self {
+            fg: self.fg.or(base.fg),
+            bg: self.bg.or(base.bg),
+            reverse: self.reverse,
+        }
+    }
+}
+
+/// A single terminal cell. `symbol` is a whole grapheme cluster; the cells
+/// covered by the right half of a wide char hold an empty symbol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    pub symbol: String,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: String::from(" "),
+            style: Style::default(),
+        }
+    }
+}
+
+/// Off-screen contents of the whole terminal, painted by the editor and then
+/// handed to a `Screen` to be drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub width: u16,
+    pub height: u16,
+    cells: Vec<Cell>,
+}
+
+impl Frame {
+    /// A blank frame with every cell painted `style`.
+    pub fn new(width: u16, height: u16, style: Style) -> Self {
+        let blank = Cell {
+            style,
+            ..Cell::default()
+        };
+        Self {
+            width,
+            height,
+            cells: vec![blank; width as usize * height as usize],
+        }
+    }
+
+    /// Puts `symbol`, `width` cells wide, at `(x, y)`. Anything that does not
+    /// fit entirely on the frame is dropped.
+    pub fn put(&mut self, x: usize, y: usize, symbol: &str, width: usize, style: Style) {
+        let columns = self.width as usize;
+        if y >= self.height as usize || x + width > columns {
+            return;
+        }
+
+        let idx = y * columns + x;
+        self.cells[idx] = Cell {
+            symbol: symbol.to_string(),
+            style,
+        };
+        for cell in &mut self.cells[idx + 1..idx + width] {
+            *cell = Cell {
+                symbol: String::new(),
+                style,
+            };
+        }
+    }
+
+    /// Writes `text` starting at `(x, y)`, clipped to the frame. Returns the
+    /// column after the last cell written.
+    pub fn print(&mut self, x: usize, y: usize, text: &str, style: Style) -> usize {
+        let mut x = x;
+        for g in text.graphemes(true) {
+            let width = g.width().max(1);
+            if x + width > self.width as usize {
+                break;
+            }
+            self.put(x, y, g, width, style);
+            x += width;
+        }
+
+        x
+    }
+
+    /// Paints `style` over the cells from `x` to the end of row `y`.
+    pub fn fill(&mut self, x: usize, y: usize, style: Style) {
+        for x in x..self.width as usize {
+            self.put(x, y, " ", 1, style);
+        }
+    }
+}
+
+fn queue_style(stdout: &mut impl Write, style: Style) -> io::Result<()> {
+    stdout.queue(SetAttribute(Attribute::Reset))?;
+    if let Some(fg) = style.fg {
+        stdout.queue(SetForegroundColor(fg))?;
+    }
+    if let Some(bg) = style.bg {
+        stdout.queue(SetBackgroundColor(bg))?;
+    }
+    if style.reverse {
+        stdout.queue(SetAttribute(Attribute::Reverse))?;
+    }
+
+    Ok(())
+}
+
+/// Draws frames to the terminal, only emitting the cells that changed since
+/// the previous one.
+#[derive(Debug, Default)]
+pub struct Screen {
+    previous: Option<Frame>,
+    cursor: (u16, u16),
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Draws `frame` and leaves the cursor at `cursor`. The first frame, or
+    /// one of a different size, is drawn in full.
+    pub fn render(&mut self, frame: Frame, cursor: (u16, u16)) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        if self.previous.as_ref() == Some(&frame) && self.cursor == cursor {
+            return stdout.flush();
+        }
+        stdout.queue(Hide)?;
+
+        let previous = match self.previous.take() {
+            Some(previous) if previous.width == frame.width && previous.height == frame.height => {
+                Some(previous)
+            }
+            _ => {
+                stdout.queue(Clear(ClearType::All))?;
+                None
+            }
+        };
+
+        let columns = frame.width as usize;
+        let mut style = None;
+        // Where the terminal cursor is after the last cell printed.
+        let mut position = None;
+        for (idx, cell) in frame.cells.iter().enumerate() {
+            // The right half of a wide char is drawn along with its left half.
+            if cell.symbol.is_empty() {
+                continue;
+            }
+            if previous.as_ref().is_some_and(|p| p.cells[idx] == *cell) {
+                continue;
+            }
+
+            let at = ((idx % columns) as u16, (idx / columns) as u16);
+            if position != Some(at) {
+                stdout.queue(MoveTo(at.0, at.1))?;
+            }
+            if style != Some(cell.style) {
+                queue_style(&mut stdout, cell.style)?;
+                style = Some(cell.style);
+            }
+            stdout.queue(Print(&cell.symbol))?;
+            let width = cell.symbol.width().max(1) as u16;
+            position = Some((at.0 + width, at.1));
+        }
+
+        stdout
+            .queue(SetAttribute(Attribute::Reset))?
+            .queue(MoveTo(cursor.0, cursor.1))?
+            .queue(Show)?;
+        stdout.flush()?;
+        self.previous = Some(frame);
+        self.cursor = cursor;
+
+        Ok(())
+    }
+}
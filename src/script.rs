@@ -0,0 +1,288 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, AST};
+
+use crate::config;
+use crate::editor::EditorState;
+use crate::textbuffer::TextBuffer;
+use crate::undo::Cursor;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Editor events scripts can hook into with `on(event, fn)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    /// A file was opened. The hook gets its path.
+    Open,
+    /// The buffer is about to be written. The hook gets the path and can still
+    /// change the text.
+    Save,
+    /// The mode changed. The hook gets the new mode and the previous one.
+    ModeChange,
+}
+
+impl Hook {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "open" => Some(Hook::Open),
+            "save" => Some(Hook::Save),
+            "mode_change" => Some(Hook::ModeChange),
+            _ => None,
+        }
+    }
+}
+
+/// What scripts see of the editor while one of their functions runs. The
+/// current buffer's text is moved in here for the duration of the call, so
+/// edits made by scripts are recorded for undo like any other.
+#[derive(Debug, Default)]
+struct Host {
+    lines: TextBuffer,
+    cursor: Cursor,
+    mode: String,
+    message: Option<String>,
+    changed: bool,
+    /// Index of the script being loaded, which the commands and hooks it
+    /// registers belong to.
+    script: usize,
+    commands: BTreeMap<String, (usize, FnPtr)>,
+    hooks: Vec<(Hook, usize, FnPtr)>,
+}
+
+impl Host {
+    fn row(&self, row: i64) -> ScriptResult<usize> {
+        usize::try_from(row)
+            .ok()
+            .filter(|row| *row < self.lines.len())
+            .ok_or_else(|| format!("Line {row} out of range").into())
+    }
+}
+
+/// Rhai scripts loaded from the config folder, and the commands and hooks
+/// they registered.
+#[derive(Debug)]
+pub struct Scripts {
+    engine: Engine,
+    scripts: Vec<AST>,
+    host: Rc<RefCell<Host>>,
+}
+
+impl Scripts {
+    /// Loads every `*.rhai` file in the config folder, in file name order.
+    /// Scripts that fail to load are skipped, and the first error returned.
+    pub fn load() -> (Self, Option<String>) {
+        let host = Rc::new(RefCell::new(Host::default()));
+        let mut scripts = Self {
+            engine: engine(&host),
+            scripts: Vec::new(),
+            host,
+        };
+
+        let mut paths: Vec<_> = config::config_dir()
+            .and_then(|dir| std::fs::read_dir(dir).ok())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "rhai"))
+            .collect();
+        paths.sort();
+
+        let mut error = None;
+        for path in paths {
+            let ast = scripts.engine.compile_file(path.clone()).and_then(|ast| {
+                scripts.host.borrow_mut().script = scripts.scripts.len();
+                scripts.engine.run_ast(&ast)?;
+                Ok(ast)
+            });
+            match ast {
+                Ok(ast) => scripts.scripts.push(ast),
+                Err(e) => {
+                    // Drop whatever the script registered before failing.
+                    let idx = scripts.scripts.len();
+                    let mut host = scripts.host.borrow_mut();
+                    host.commands.retain(|_, (script, _)| *script != idx);
+                    host.hooks.retain(|(_, script, _)| *script != idx);
+                    error = error.or(Some(format!("{}: {e}", path.display())));
+                }
+            }
+        }
+
+        (scripts, error)
+    }
+
+    fn call(&self, (script, f): &(usize, FnPtr), args: impl FuncArgs) -> ScriptResult<()> {
+        f.call::<Dynamic>(&self.engine, &self.scripts[*script], args)
+            .map(drop)
+    }
+}
+
+/// An engine with the editor API registered, working on `host`.
+fn engine(host: &Rc<RefCell<Host>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let h = host.clone();
+    engine.on_print(move |text| h.borrow_mut().message = Some(text.to_string()));
+    let h = host.clone();
+    engine.on_debug(move |text, _, _| h.borrow_mut().message = Some(text.to_string()));
+
+    let h = host.clone();
+    engine.register_fn("line_count", move || h.borrow().lines.len() as i64);
+    let h = host.clone();
+    engine.register_fn("get_line", move |row: i64| -> ScriptResult<String> {
+        let host = h.borrow();
+        Ok(host.lines.line(host.row(row)?))
+    });
+    let h = host.clone();
+    engine.register_fn(
+        "set_line",
+        move |row: i64, text: &str| -> ScriptResult<()> {
+            let mut host = h.borrow_mut();
+            let row = host.row(row)?;
+            host.lines.remove_lines(row, row + 1);
+            host.lines.insert_line(row, text);
+            host.changed = true;
+            Ok(())
+        },
+    );
+    let h = host.clone();
+    engine.register_fn(
+        "insert_line",
+        move |row: i64, text: &str| -> ScriptResult<()> {
+            let mut host = h.borrow_mut();
+            let row = match usize::try_from(row) {
+                Ok(row) if row <= host.lines.len() => row,
+                _ => return Err(format!("Line {row} out of range").into()),
+            };
+            host.lines.insert_line(row, text);
+            host.changed = true;
+            Ok(())
+        },
+    );
+    let h = host.clone();
+    engine.register_fn("delete_line", move |row: i64| -> ScriptResult<()> {
+        let mut host = h.borrow_mut();
+        let row = host.row(row)?;
+        host.lines.remove_lines(row, row + 1);
+        host.changed = true;
+        Ok(())
+    });
+
+    let h = host.clone();
+    engine.register_fn("cursor_row", move || h.borrow().cursor.1 as i64);
+    let h = host.clone();
+    engine.register_fn("cursor_col", move || h.borrow().cursor.0 as i64);
+    let h = host.clone();
+    engine.register_fn("set_cursor", move |row: i64, col: i64| {
+        h.borrow_mut().cursor = (col.max(0) as usize, row.max(0) as usize);
+    });
+
+    let h = host.clone();
+    engine.register_fn("mode", move || h.borrow().mode.clone());
+    let h = host.clone();
+    engine.register_fn("message", move || {
+        h.borrow().message.clone().unwrap_or_default()
+    });
+    let h = host.clone();
+    engine.register_fn("set_message", move |text: &str| {
+        h.borrow_mut().message = Some(text.to_string());
+    });
+
+    let h = host.clone();
+    engine.register_fn(
+        "register_command",
+        move |name: &str, f: FnPtr| -> ScriptResult<()> {
+            if !name.starts_with(|c: char| c.is_ascii_uppercase())
+                || !name.chars().all(|c| c.is_ascii_alphabetic())
+            {
+                return Err(format!(
+                    "Command names must be letters, starting with a capital: {name}"
+                )
+                .into());
+            }
+            let mut host = h.borrow_mut();
+            let script = host.script;
+            host.commands.insert(name.to_string(), (script, f));
+            Ok(())
+        },
+    );
+    let h = host.clone();
+    engine.register_fn("on", move |event: &str, f: FnPtr| -> ScriptResult<()> {
+        let hook = Hook::from_name(event).ok_or_else(|| format!("Unknown event: {event}"))?;
+        let mut host = h.borrow_mut();
+        let script = host.script;
+        host.hooks.push((hook, script, f));
+        Ok(())
+    });
+
+    engine
+}
+
+impl EditorState {
+    /// Runs `f` with the editor state handed over to scripts, then takes
+    /// back whatever they changed. Script errors end up in the message.
+    fn editor_with_scripts(&mut self, f: impl FnOnce(&Scripts) -> ScriptResult<()>) {
+        {
+            let mut host = self.scripts.host.borrow_mut();
+            host.lines = std::mem::take(&mut self.buffer.row);
+            host.cursor = (self.cx, self.cy);
+            host.mode = self.mode.to_string();
+            host.message = self.message.take();
+            host.changed = false;
+        }
+
+        let result = f(&self.scripts);
+
+        let mut host = self.scripts.host.borrow_mut();
+        self.buffer.row = std::mem::take(&mut host.lines);
+        self.buffer.dirty |= host.changed;
+        self.message = host.message.take();
+        let (cx, cy) = host.cursor;
+        drop(host);
+
+        self.cy = cy.min(self.buffer.row.len().saturating_sub(1));
+        self.cx = if self.buffer.row.is_empty() {
+            0
+        } else {
+            cx.min(self.buffer.row.line_len(self.cy))
+        };
+        if let Err(e) = result {
+            self.message = Some(format!("Script error: {e}"));
+        }
+    }
+
+    /// Calls the functions scripts registered for `hook`, in the order they
+    /// were registered.
+    pub fn editor_run_hooks(&mut self, hook: Hook, args: impl FuncArgs + Clone) {
+        let hooks: Vec<_> = self
+            .scripts
+            .host
+            .borrow()
+            .hooks
+            .iter()
+            .filter(|(h, _, _)| *h == hook)
+            .map(|(_, script, f)| (*script, f.clone()))
+            .collect();
+        if hooks.is_empty() {
+            return;
+        }
+
+        self.editor_with_scripts(|scripts| {
+            hooks.iter().try_for_each(|f| scripts.call(f, args.clone()))
+        });
+    }
+
+    /// Runs the command a script registered as `name`, passing it `args` or
+    /// an empty string.
+    pub fn editor_run_user_command(&mut self, name: &str, args: Option<&str>) {
+        let command = self.scripts.host.borrow().commands.get(name).cloned();
+        let Some(command) = command else {
+            self.message = Some(format!("Not an editor command: {name}"));
+            return;
+        };
+
+        let args = args.unwrap_or_default().to_string();
+        self.editor_with_scripts(|scripts| scripts.call(&command, (args,)));
+    }
+}
@@ -0,0 +1,180 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::prompt::Prompt;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::textbuffer::{self, TextBuffer};
+use crate::undo::Cursor;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl Direction {
+    pub fn reverse(self) -> Self {
+        match self {
+            Direction::Forward => Direction::Backward,
+            Direction::Backward => Direction::Forward,
+        }
+    }
+}
+
+fn is_keyword(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// A compiled search pattern.
+///
+/// Patterns use the `regex` crate syntax, plus vim's `\<` and `\>` word
+/// boundaries.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub text: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn new(text: &str) -> Result<Self, String> {
+        Self::build(text, false)
+    }
+
+    /// Compiles `text`, ignoring case when `ignore_case` is set.
+    pub fn build(text: &str, ignore_case: bool) -> Result<Self, String> {
+        let translated = text.replace("\\<", "\\b").replace("\\>", "\\b");
+        let regex = RegexBuilder::new(&translated)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|_| format!("Invalid pattern: {text}"))?;
+
+        Ok(Self {
+            text: text.to_string(),
+            regex,
+        })
+    }
+
+    /// Matches `word` only where it is not part of a longer keyword, as
+    /// used by `*` and `#`.
+    pub fn word(word: &str) -> Self {
+        Self::new(&format!("\\<{}\\>", regex::escape(word))).unwrap()
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// Every match in `line` as `(start, end)` columns, end exclusive.
+    pub fn matches(&self, line: &str) -> Vec<(usize, usize)> {
+        self.regex
+            .find_iter(line)
+            .map(|m| {
+                (
+                    textbuffer::byte_to_col(line, m.start()),
+                    textbuffer::byte_to_col(line, m.end()),
+                )
+            })
+            .collect()
+    }
+}
+
+/// A match found by `find`, and whether the search wrapped around the end
+/// (or start) of the buffer to get there.
+pub struct Found {
+    pub at: Cursor,
+    pub wrapped: bool,
+}
+
+/// Finds the closest match of `pattern` strictly after (or before) `from`,
+/// wrapping around the buffer.
+pub fn find(
+    buffer: &TextBuffer,
+    pattern: &Pattern,
+    from: Cursor,
+    direction: Direction,
+) -> Option<Found> {
+    let numrows = buffer.len();
+    if numrows == 0 {
+        return None;
+    }
+    let (cx, cy) = from;
+    let cy = cy.min(numrows - 1);
+
+    for step in 0..=numrows {
+        let (row, wrapped) = match direction {
+            Direction::Forward => ((cy + step) % numrows, cy + step >= numrows),
+            Direction::Backward => ((cy + numrows - step) % numrows, step > cy),
+        };
+        // The starting row is searched twice: first past the cursor, then,
+        // once everything else was searched, up to the cursor.
+        let allowed = |col: usize| match (step, direction) {
+            (0, Direction::Forward) => col > cx,
+            (0, Direction::Backward) => col < cx,
+            (s, Direction::Forward) if s == numrows => col <= cx,
+            (s, Direction::Backward) if s == numrows => col >= cx,
+            _ => true,
+        };
+        let mut starts = pattern.matches(&buffer.line(row)).into_iter().map(|m| m.0);
+        let col = match direction {
+            Direction::Forward => starts.find(|&col| allowed(col)),
+            Direction::Backward => starts.rev().find(|&col| allowed(col)),
+        };
+
+        if let Some(col) = col {
+            return Some(Found {
+                at: (col, row),
+                wrapped,
+            });
+        }
+    }
+
+    None
+}
+
+/// The keyword under or after the cursor, used by `*` and `#`.
+pub fn word_at(line: &str, cx: usize) -> Option<(usize, String)> {
+    let chars: Vec<char> = line
+        .graphemes(true)
+        .filter_map(|g| g.chars().next())
+        .collect();
+    let mut start = cx.min(chars.len());
+    while start < chars.len() && !is_keyword(chars[start]) {
+        start += 1;
+    }
+    if start == chars.len() {
+        return None;
+    }
+    while start > 0 && is_keyword(chars[start - 1]) {
+        start -= 1;
+    }
+    let word: String = chars[start..]
+        .iter()
+        .take_while(|c| is_keyword(**c))
+        .collect();
+
+    Some((start, word))
+}
+
+/// State of the `/` and `?` prompt and the last search made.
+#[derive(Debug)]
+pub struct SearchState {
+    pub prompt: Prompt,
+    pub direction: Direction,
+    pub last: Option<Pattern>,
+    /// Whether matches of the last search are highlighted.
+    pub highlight: bool,
+    /// Cursor and scroll offsets to restore when the search is cancelled.
+    pub origin: (Cursor, usize, usize),
+}
+
+impl SearchState {
+    pub fn new() -> Self {
+        Self {
+            prompt: Prompt::new(),
+            direction: Direction::Forward,
+            last: None,
+            highlight: false,
+            origin: ((0, 0), 0, 0),
+        }
+    }
+}
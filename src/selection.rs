@@ -0,0 +1,66 @@
+use crate::undo::Cursor;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionKind {
+    Charwise,
+    Linewise,
+    Blockwise,
+}
+
+/// A region of the buffer between two cursor positions, both inclusive.
+///
+/// `start` always comes before `end` in the buffer. For blockwise selections
+/// the columns are normalised independently, so `start` is the top left
+/// corner and `end` the bottom right one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selection {
+    pub kind: SelectionKind,
+    pub start: Cursor,
+    pub end: Cursor,
+}
+
+impl Selection {
+    pub fn new(kind: SelectionKind, anchor: Cursor, cursor: Cursor) -> Self {
+        let (ax, ay) = anchor;
+        let (cx, cy) = cursor;
+        let (start, end) = match kind {
+            SelectionKind::Blockwise => ((ax.min(cx), ay.min(cy)), (ax.max(cx), ay.max(cy))),
+            _ if (ay, ax) <= (cy, cx) => (anchor, cursor),
+            _ => (cursor, anchor),
+        };
+
+        Self { kind, start, end }
+    }
+
+    /// First and last row covered by the selection.
+    pub fn rows(&self) -> (usize, usize) {
+        (self.start.1, self.end.1)
+    }
+
+    /// Columns `(start, end)` of row `cy` covered by the selection, with `end`
+    /// exclusive. An `end` past `line_len` means the row's newline is selected
+    /// too.
+    pub fn span(&self, cy: usize, line_len: usize) -> Option<(usize, usize)> {
+        let (first, last) = self.rows();
+        if cy < first || cy > last {
+            return None;
+        }
+
+        match self.kind {
+            SelectionKind::Linewise => Some((0, line_len + 1)),
+            SelectionKind::Blockwise => {
+                let start = self.start.0.min(line_len);
+                Some((start, (self.end.0 + 1).min(line_len).max(start)))
+            }
+            SelectionKind::Charwise => {
+                let start = if cy == first { self.start.0 } else { 0 };
+                let end = if cy == last {
+                    self.end.0 + 1
+                } else {
+                    line_len + 1
+                };
+                Some((start.min(line_len), end.min(line_len + 1)))
+            }
+        }
+    }
+}
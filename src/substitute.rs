@@ -0,0 +1,235 @@
+use crossterm::event::{KeyCode, KeyEvent};
+
+use crate::command::LineRange;
+use crate::editor::EditorState;
+use crate::search::Pattern;
+use crate::textbuffer::{byte_to_col, col_to_byte, grapheme_len};
+
+/// Turns a vim replacement string into a `regex` expansion template: `&` and
+/// `\0` are the whole match, `\1`-`\9` capture groups and `\n` or `\r` a line
+/// break.
+fn template(replacement: &str) -> String {
+    let mut out = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => out.push_str(&format!("${{{d}}}")),
+                Some('n' | 'r') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('$') => out.push_str("$$"),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// A `:s` command in progress. With the `c` flag it stays around between
+/// key presses while each match is confirmed.
+#[derive(Debug)]
+pub struct Substitution {
+    pattern: Pattern,
+    replacement: String,
+    template: String,
+    global: bool,
+    confirm: bool,
+    // Where the next match is looked for, as a row and char column.
+    row: usize,
+    col: usize,
+    // Last row of the range, moved along when replacements add rows.
+    last: usize,
+    count: usize,
+    rows_changed: usize,
+    last_changed: Option<usize>,
+}
+
+impl EditorState {
+    /// Runs `:[range]s/pattern/replacement/[flags]`.
+    pub fn editor_substitute(
+        &mut self,
+        range: LineRange,
+        pattern: &str,
+        replacement: &str,
+        flags: &str,
+    ) {
+        let (first, last) = match range.resolve(self.cy, self.buffer.row.len(), self.buffer.visual)
+        {
+            Ok(rows) => rows,
+            Err(msg) => {
+                self.message = Some(msg);
+                return;
+            }
+        };
+
+        // An empty pattern reuses the last search.
+        let text = if pattern.is_empty() {
+            match &self.search.last {
+                Some(last) => last.text.clone(),
+                None => {
+                    self.message = Some(String::from("No previous search pattern"));
+                    return;
+                }
+            }
+        } else {
+            pattern.to_string()
+        };
+        let pattern = match Pattern::build(&text, flags.contains('i')) {
+            Ok(pattern) => pattern,
+            Err(msg) => {
+                self.message = Some(msg);
+                return;
+            }
+        };
+        self.registers.last_search = Some(text);
+        self.search.last = Some(pattern.clone());
+        self.search.highlight = true;
+
+        self.substitute = Some(Substitution {
+            pattern,
+            replacement: replacement.to_string(),
+            template: template(replacement),
+            global: flags.contains('g'),
+            confirm: flags.contains('c'),
+            row: first,
+            col: 0,
+            last,
+            count: 0,
+            rows_changed: 0,
+            last_changed: None,
+        });
+        self.editor_substitute_step();
+    }
+
+    /// Finds the next match from where the substitution left off, as a row
+    /// and a byte range into it.
+    fn editor_substitute_find(&mut self) -> Option<(usize, usize, usize)> {
+        let sub = self.substitute.as_mut()?;
+        while sub.row <= sub.last && sub.row < self.buffer.row.len() {
+            let line = self.buffer.row.line(sub.row);
+            let byte = col_to_byte(&line, sub.col);
+            if sub.col <= grapheme_len(&line) {
+                if let Some(m) = sub.pattern.regex().find_at(&line, byte) {
+                    return Some((sub.row, m.start(), m.end()));
+                }
+            }
+            sub.row += 1;
+            sub.col = 0;
+        }
+
+        None
+    }
+
+    /// Moves past the match at `(row, start..end)` without replacing it.
+    fn editor_substitute_skip(&mut self, row: usize, start: usize, end: usize) {
+        let line = self.buffer.row.line(row);
+        let sub = self.substitute.as_mut().unwrap();
+        if sub.global {
+            sub.col = byte_to_col(&line, end) + usize::from(start == end);
+        } else {
+            sub.row = row + 1;
+            sub.col = 0;
+        }
+    }
+
+    fn editor_substitute_replace(&mut self, row: usize, start: usize, end: usize) {
+        let line = self.buffer.row.line(row);
+        let sub = self.substitute.as_mut().unwrap();
+        let caps = sub.pattern.regex().captures_at(&line, start).unwrap();
+        let mut replacement = String::new();
+        caps.expand(&sub.template, &mut replacement);
+
+        let col = byte_to_col(&line, start);
+        let end_col = byte_to_col(&line, end);
+        self.buffer.row.remove_between((row, col), (row, end_col));
+        self.buffer.row.insert_str(row, col, &replacement);
+        self.buffer.dirty = true;
+
+        sub.count += 1;
+        if sub.last_changed != Some(row) {
+            sub.rows_changed += 1;
+        }
+        // Line breaks in the replacement push the rest of the range down.
+        let added = replacement.matches('\n').count();
+        let tail = replacement.rsplit('\n').next().unwrap_or("");
+        sub.row = row + added;
+        sub.last += added;
+        sub.last_changed = Some(sub.row);
+        sub.col = if added > 0 {
+            grapheme_len(tail)
+        } else {
+            col + grapheme_len(&replacement)
+        } + usize::from(start == end);
+        if !sub.global {
+            sub.row += 1;
+            sub.col = 0;
+        }
+    }
+
+    /// Replaces matches until one needs confirming or the range is done.
+    fn editor_substitute_step(&mut self) {
+        while let Some((row, start, end)) = self.editor_substitute_find() {
+            let sub = self.substitute.as_ref().unwrap();
+            if sub.confirm {
+                let line = self.buffer.row.line(row);
+                self.cy = row;
+                self.cx = byte_to_col(&line, start);
+                self.message = Some(format!("replace with {} (y/n/a/q/l)?", sub.replacement));
+                return;
+            }
+            self.editor_substitute_replace(row, start, end);
+        }
+        self.editor_substitute_finish();
+    }
+
+    fn editor_substitute_finish(&mut self) {
+        let Some(sub) = self.substitute.take() else {
+            return;
+        };
+
+        if let Some(row) = sub.last_changed {
+            self.cy = row.min(self.buffer.row.len().saturating_sub(1));
+            self.cx = 0;
+        }
+        self.message = match (sub.count, sub.rows_changed) {
+            (0, _) if !sub.confirm => Some(format!("Pattern not found: {}", sub.pattern.text)),
+            (0, _) => None,
+            (1, _) => Some(String::from("1 substitution on 1 line")),
+            (count, 1) => Some(format!("{count} substitutions on 1 line")),
+            (count, rows) => Some(format!("{count} substitutions on {rows} lines")),
+        };
+    }
+
+    /// Answers the confirmation prompt of a `:s///c` command.
+    pub fn editor_confirm_key(&mut self, key: KeyEvent) {
+        let Some((row, start, end)) = self.editor_substitute_find() else {
+            return self.editor_substitute_finish();
+        };
+
+        match key.code {
+            KeyCode::Char('y') => {
+                self.editor_substitute_replace(row, start, end);
+                self.editor_substitute_step();
+            }
+            KeyCode::Char('l') => {
+                self.editor_substitute_replace(row, start, end);
+                self.editor_substitute_finish();
+            }
+            KeyCode::Char('n') => {
+                self.editor_substitute_skip(row, start, end);
+                self.editor_substitute_step();
+            }
+            KeyCode::Char('a') => {
+                self.substitute.as_mut().unwrap().confirm = false;
+                self.editor_substitute_step();
+            }
+            KeyCode::Char('q') | KeyCode::Esc => self.editor_substitute_finish(),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use std::path::Path;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::editor::{EditorState, Highlight};
+use crate::textbuffer::TextBuffer;
+use crate::treesitter::SyntaxTree;
+
+/// How to highlight one language.
+#[derive(Debug)]
+pub struct Syntax {
+    /// File extensions, without the dot. Names such as `Makefile` are
+    /// matched as a whole.
+    extensions: &'static [&'static str],
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Chars that open and close a string literal.
+    quotes: &'static [char],
+    /// Whether a string left open at the end of a row goes on in the next.
+    multiline_strings: bool,
+}
+
+const LANGUAGES: [Syntax; 8] = [
+    Syntax {
+        extensions: &["rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+            "trait", "true", "type", "unsafe", "use", "where", "while",
+        ],
+        types: &[
+            "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "str", "u8",
+            "u16", "u32", "u64", "u128", "usize", "String", "Vec", "Option", "Result", "Box",
+            "Some", "None", "Ok", "Err",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        multiline_strings: true,
+    },
+    Syntax {
+        extensions: &["c", "h", "cc", "cpp", "cxx", "hpp", "hh"],
+        keywords: &[
+            "break",
+            "case",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "enum",
+            "extern",
+            "for",
+            "goto",
+            "if",
+            "inline",
+            "namespace",
+            "new",
+            "private",
+            "protected",
+            "public",
+            "return",
+            "sizeof",
+            "static",
+            "struct",
+            "switch",
+            "template",
+            "this",
+            "typedef",
+            "union",
+            "using",
+            "virtual",
+            "volatile",
+            "while",
+            "#include",
+            "#define",
+            "#ifdef",
+            "#ifndef",
+            "#endif",
+            "#if",
+            "#else",
+            "#pragma",
+        ],
+        types: &[
+            "auto", "bool", "char", "double", "float", "int", "long", "short", "signed",
+            "unsigned", "void", "size_t", "NULL", "nullptr", "true", "false",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        multiline_strings: false,
+    },
+    Syntax {
+        extensions: &["py", "pyw"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in",
+            "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+            "with", "yield",
+        ],
+        types: &[
+            "True", "False", "None", "self", "int", "float", "str", "bytes", "bool", "list",
+            "dict", "set", "tuple",
+        ],
+        line_comment: Some("#"),
+        block_comment: None,
+        quotes: &['"', '\''],
+        multiline_strings: false,
+    },
+    Syntax {
+        extensions: &["js", "mjs", "cjs", "jsx", "ts", "tsx"],
+        keywords: &[
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "export",
+            "extends",
+            "finally",
+            "for",
+            "from",
+            "function",
+            "if",
+            "import",
+            "in",
+            "instanceof",
+            "interface",
+            "let",
+            "new",
+            "of",
+            "return",
+            "static",
+            "switch",
+            "this",
+            "throw",
+            "try",
+            "type",
+            "typeof",
+            "var",
+            "while",
+            "yield",
+        ],
+        types: &[
+            "true",
+            "false",
+            "null",
+            "undefined",
+            "number",
+            "string",
+            "boolean",
+            "any",
+            "void",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        multiline_strings: false,
+    },
+    Syntax {
+        extensions: &["go"],
+        keywords: &[
+            "break",
+            "case",
+            "chan",
+            "const",
+            "continue",
+            "default",
+            "defer",
+            "else",
+            "fallthrough",
+            "for",
+            "func",
+            "go",
+            "goto",
+            "if",
+            "import",
+            "interface",
+            "map",
+            "package",
+            "range",
+            "return",
+            "select",
+            "struct",
+            "switch",
+            "type",
+            "var",
+        ],
+        types: &[
+            "bool", "byte", "error", "float32", "float64", "int", "int8", "int16", "int32",
+            "int64", "rune", "string", "uint", "uint8", "uint16", "uint32", "uint64", "uintptr",
+            "true", "false", "nil", "iota",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        multiline_strings: false,
+    },
+    Syntax {
+        extensions: &["sh", "bash", "zsh"],
+        keywords: &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+            "in", "local", "return", "then", "until", "while",
+        ],
+        types: &[],
+        line_comment: Some("#"),
+        block_comment: None,
+        quotes: &['"', '\''],
+        multiline_strings: true,
+    },
+    Syntax {
+        extensions: &["toml"],
+        keywords: &[],
+        types: &["true", "false"],
+        line_comment: Some("#"),
+        block_comment: None,
+        quotes: &['"', '\''],
+        multiline_strings: false,
+    },
+    Syntax {
+        extensions: &["json"],
+        keywords: &[],
+        types: &["true", "false", "null"],
+        line_comment: None,
+        block_comment: None,
+        quotes: &['"'],
+        multiline_strings: false,
+    },
+];
+
+/// The language of `path`, going by its extension.
+fn for_path(path: &str) -> Option<&'static Syntax> {
+    let path = Path::new(path);
+    let ext = path.extension().or_else(|| path.file_name())?.to_str()?;
+    LANGUAGES
+        .iter()
+        .find(|syntax| syntax.extensions.contains(&ext))
+}
+
+/// What a row leaves open for the next one.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum State {
+    #[default]
+    Normal,
+    Comment,
+    String(char),
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || ",.()+-/*=~%<>[];{}:&|!^?@".contains(c)
+}
+
+/// Whether `token` starts at `chars[i]`.
+fn starts_with(chars: &[char], i: usize, token: &str) -> bool {
+    let mut rest = chars[i..].iter();
+    token.chars().all(|c| rest.next() == Some(&c))
+}
+
+impl Syntax {
+    /// Highlights `line`, one entry per char, starting in `state`. Returns
+    /// the state the row ends in.
+    fn highlight(&self, line: &str, mut state: State) -> (Vec<Highlight>, State) {
+        let chars: Vec<char> = line.chars().collect();
+        let mut hl = vec![Highlight::Normal; chars.len()];
+        let mut prev_sep = true;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            match state {
+                State::Comment => {
+                    let end = self.block_comment.map_or("", |(_, end)| end);
+                    if starts_with(&chars, i, end) {
+                        let len = end.chars().count();
+                        hl[i..i + len].fill(Highlight::Comment);
+                        i += len;
+                        state = State::Normal;
+                        prev_sep = true;
+                    } else {
+                        hl[i] = Highlight::Comment;
+                        i += 1;
+                    }
+                    continue;
+                }
+                State::String(quote) => {
+                    hl[i] = Highlight::String;
+                    if c == '\\' && i + 1 < chars.len() {
+                        hl[i + 1] = Highlight::String;
+                        i += 2;
+                        continue;
+                    }
+                    if c == quote {
+                        state = State::Normal;
+                        prev_sep = true;
+                    }
+                    i += 1;
+                    continue;
+                }
+                State::Normal => {}
+            }
+
+            if let Some(comment) = self.line_comment {
+                if starts_with(&chars, i, comment) {
+                    hl[i..].fill(Highlight::Comment);
+                    break;
+                }
+            }
+            if let Some((start, _)) = self.block_comment {
+                if starts_with(&chars, i, start) {
+                    let len = start.chars().count();
+                    hl[i..i + len].fill(Highlight::Comment);
+                    i += len;
+                    state = State::Comment;
+                    continue;
+                }
+            }
+            if self.quotes.contains(&c) {
+                hl[i] = Highlight::String;
+                state = State::String(c);
+                i += 1;
+                continue;
+            }
+
+            let prev = if i > 0 { hl[i - 1] } else { Highlight::Normal };
+            if (c.is_ascii_digit() && (prev_sep || prev == Highlight::Number))
+                || (c == '.' && prev == Highlight::Number)
+            {
+                hl[i] = Highlight::Number;
+                prev_sep = false;
+                i += 1;
+                continue;
+            }
+
+            if prev_sep {
+                let words = self.keywords.iter().map(|w| (w, Highlight::Keyword));
+                let words = words.chain(self.types.iter().map(|w| (w, Highlight::Type)));
+                let word = words.into_iter().find(|(word, _)| {
+                    let len = word.chars().count();
+                    starts_with(&chars, i, word)
+                        && chars.get(i + len).is_none_or(|c| is_separator(*c))
+                });
+                if let Some((word, highlight)) = word {
+                    let len = word.chars().count();
+                    hl[i..i + len].fill(highlight);
+                    i += len;
+                    prev_sep = false;
+                    continue;
+                }
+            }
+
+            prev_sep = is_separator(c);
+            i += 1;
+        }
+
+        if matches!(state, State::String(_)) && !self.multiline_strings {
+            state = State::Normal;
+        }
+        (hl, state)
+    }
+}
+
+/// Highlighting of a buffer's rows, worked out from the top as far as they
+/// have been drawn and kept until an edit invalidates them. Languages with a
+/// bundled tree-sitter grammar are highlighted from their syntax tree, the
+/// others with a `Syntax`.
+#[derive(Debug, Default)]
+pub struct Highlighter {
+    syntax: Option<&'static Syntax>,
+    tree: Option<SyntaxTree>,
+    /// One entry per grapheme of each highlighted row.
+    rows: Vec<Vec<Highlight>>,
+    /// State at the end of each highlighted row.
+    states: Vec<State>,
+}
+
+impl Highlighter {
+    pub fn for_path(path: &str) -> Self {
+        Self {
+            syntax: for_path(path),
+            tree: SyntaxTree::for_path(path),
+            ..Self::default()
+        }
+    }
+
+    /// Highlighting of `row`, one entry per grapheme, or an empty slice when
+    /// it has not been worked out.
+    pub fn row(&self, row: usize) -> &[Highlight] {
+        self.rows.get(row).map_or(&[], Vec::as_slice)
+    }
+
+    /// The syntax tree of the buffer, if it has a grammar. See `sync`.
+    pub fn tree(&self) -> Option<&SyntaxTree> {
+        self.tree.as_ref().filter(|tree| tree.is_parsed())
+    }
+
+    /// Catches up with the edits made to `text`, dropping the highlighting
+    /// of every row from the first one they changed.
+    pub fn sync(&mut self, text: &mut TextBuffer) {
+        let mut changed = text.take_changed();
+        if let Some(row) = self.tree.as_mut().and_then(|tree| tree.parse(text)) {
+            changed = Some(changed.map_or(row, |changed| changed.min(row)));
+        }
+        if let Some(row) = changed {
+            self.rows.truncate(row);
+            self.states.truncate(row);
+        }
+    }
+
+    /// Highlights the rows of `text` up to `end` that are not already.
+    pub fn update(&mut self, text: &mut TextBuffer, end: usize) {
+        self.sync(text);
+        let rows = self.rows.len()..end.min(text.len());
+
+        if let Some(tree) = self.tree() {
+            if rows.is_empty() {
+                return;
+            }
+            let highlighted = tree.highlight(text, rows.clone());
+            for (row, bytes) in rows.zip(highlighted) {
+                let line = text.line(row);
+                // Graphemes take the highlight of their first byte.
+                let graphemes = line.grapheme_indices(true).map(|(idx, _)| bytes[idx]);
+                self.rows.push(graphemes.collect());
+                self.states.push(State::Normal);
+            }
+            return;
+        }
+        let Some(syntax) = self.syntax else {
+            return;
+        };
+
+        for row in rows {
+            let line = text.line(row);
+            let state = self.states.last().copied().unwrap_or_default();
+            let (chars, state) = syntax.highlight(&line, state);
+            // Graphemes take the highlight of their first char.
+            let mut idx = 0;
+            let graphemes = line.graphemes(true).map(|g| {
+                let hl = chars[idx];
+                idx += g.chars().count();
+                hl
+            });
+            self.rows.push(graphemes.collect());
+            self.states.push(state);
+        }
+    }
+}
+
+impl EditorState {
+    /// Highlights what every window is about to show.
+    pub fn editor_update_syntax(&mut self) {
+        let views: Vec<(usize, usize)> = self
+            .windows
+            .iter()
+            .map(|window| {
+                // The current window's fields are only stored when it loses
+                // focus, so its buffer and view are read from the editor.
+                let (buffer, rowoff) = if window.id == self.window {
+                    (self.buffer.id, self.rowoff)
+                } else {
+                    (window.buffer, window.offset.0)
+                };
+                (buffer, rowoff + window.rect.height as usize)
+            })
+            .collect();
+
+        for (id, end) in views {
+            let buffer = self.editor_buffer_mut(id);
+            buffer.syntax.update(&mut buffer.row, end);
+        }
+    }
+}
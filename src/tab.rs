@@ -0,0 +1,180 @@
+use crate::editor::EditorState;
+use crate::screen::Frame;
+use crate::window::{Layout, Window};
+
+/// The windows of a tab page that is not on screen. The tab page being shown
+/// keeps its windows in `EditorState` instead.
+#[derive(Debug)]
+pub struct TabPage {
+    pub layout: Layout,
+    pub windows: Vec<Window>,
+    /// Id of the window that was current when the tab page was left.
+    pub window: usize,
+}
+
+impl TabPage {
+    fn buffer(&self) -> usize {
+        self.windows
+            .iter()
+            .find(|window| window.id == self.window)
+            .map_or(0, |window| window.buffer)
+    }
+}
+
+impl EditorState {
+    /// Number of tab pages, counting the one on screen.
+    pub fn editor_tab_count(&self) -> usize {
+        self.tabs.len() + 1
+    }
+
+    /// Rows at the top of the screen taken by the tab line, which is only
+    /// shown once there are several tab pages.
+    pub fn editor_tabline_height(&self) -> u16 {
+        u16::from(!self.tabs.is_empty())
+    }
+
+    /// Whether closing the current window leaves others on screen, in this
+    /// tab page or another.
+    pub fn editor_has_other_windows(&self) -> bool {
+        self.windows.len() > 1 || !self.tabs.is_empty()
+    }
+
+    /// Whether a window other than the current one shows the current buffer,
+    /// in any tab page.
+    pub fn editor_buffer_shown_elsewhere(&self) -> bool {
+        let id = self.buffer.id;
+        self.windows
+            .iter()
+            .any(|window| window.id != self.window && window.buffer == id)
+            || self
+                .tabs
+                .iter()
+                .any(|tab| tab.windows.iter().any(|window| window.buffer == id))
+    }
+
+    fn editor_store_tab(&mut self) -> TabPage {
+        self.editor_store_window();
+        TabPage {
+            layout: std::mem::replace(&mut self.layout, Layout::Window(0)),
+            windows: std::mem::take(&mut self.windows),
+            window: self.window,
+        }
+    }
+
+    fn editor_load_tab(&mut self, tab: TabPage) {
+        self.layout = tab.layout;
+        self.windows = tab.windows;
+        self.editor_load_window(tab.window);
+        self.editor_layout();
+    }
+
+    /// Shows the tab page at `idx`, counting from 0.
+    pub fn editor_goto_tab(&mut self, idx: usize) {
+        if idx == self.tab || idx >= self.editor_tab_count() {
+            return;
+        }
+
+        let current = self.editor_store_tab();
+        self.tabs.insert(self.tab, current);
+        let tab = self.tabs.remove(idx);
+        self.tab = idx;
+        self.editor_load_tab(tab);
+    }
+
+    /// `gt`: goes to the next tab page, or to tab page `count`.
+    pub fn editor_next_tab(&mut self, count: Option<usize>) {
+        let idx = match count {
+            Some(count) => count.saturating_sub(1),
+            None => (self.tab + 1) % self.editor_tab_count(),
+        };
+        self.editor_goto_tab(idx);
+    }
+
+    /// `gT`: goes `count` tab pages back, wrapping around.
+    pub fn editor_previous_tab(&mut self, count: usize) {
+        let len = self.editor_tab_count();
+        self.editor_goto_tab((self.tab + len - count % len) % len);
+    }
+
+    /// Opens a tab page after the current one, with a single window showing
+    /// `file` or a new empty buffer.
+    pub fn editor_new_tab(&mut self, file: Option<String>) {
+        let current = self.editor_store_tab();
+        self.tabs.insert(self.tab, current);
+        self.tab += 1;
+
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.layout = Layout::Window(id);
+        self.windows = vec![Window {
+            id,
+            buffer: self.buffer.id,
+            cursor: (self.cx, self.cy),
+            offset: (self.rowoff, self.coloff),
+            rect: Default::default(),
+        }];
+        self.window = id;
+        self.editor_layout();
+
+        match file {
+            Some(file) => self.editor_open(&file),
+            None => self.editor_new_buffer(),
+        }
+    }
+
+    /// Closes the current tab page and its windows. Buffers with unsaved
+    /// changes that no other tab page shows need `force`.
+    pub fn editor_close_tab(&mut self, force: bool) {
+        if self.tabs.is_empty() {
+            self.message = Some(String::from("Cannot close last tab page"));
+            return;
+        }
+        self.editor_store_window();
+        let unsaved = self.windows.iter().find_map(|window| {
+            let buffer = self.editor_buffer(window.buffer);
+            let elsewhere = self
+                .tabs
+                .iter()
+                .any(|tab| tab.windows.iter().any(|w| w.buffer == buffer.id));
+            (buffer.dirty && !elsewhere).then_some(buffer)
+        });
+        if let (Some(buffer), false) = (unsaved, force) {
+            self.message = Some(format!(
+                "No write since last change for buffer {} \"{}\" (add ! to override)",
+                buffer.id,
+                buffer.filename.as_deref().unwrap_or("[No Name]")
+            ));
+            return;
+        }
+
+        self.editor_store_tab();
+        // The tab page after the closed one takes its place.
+        let idx = self.tab.min(self.tabs.len() - 1);
+        let tab = self.tabs.remove(idx);
+        self.tab = idx;
+        self.editor_load_tab(tab);
+    }
+
+    /// Draws the tab line, with a label per tab page naming the buffer of
+    /// its current window.
+    pub fn editor_draw_tabline(&self, frame: &mut Frame) {
+        let inactive = self.theme.tab_inactive;
+        let mut x = 0;
+        for idx in 0..self.editor_tab_count() {
+            let buffer = match idx {
+                idx if idx == self.tab => &self.buffer,
+                idx if idx < self.tab => self.editor_buffer(self.tabs[idx].buffer()),
+                idx => self.editor_buffer(self.tabs[idx - 1].buffer()),
+            };
+            let name = buffer.filename.as_deref().unwrap_or("[No Name]");
+            let dirty = if buffer.dirty { " [+]" } else { "" };
+            let style = if idx == self.tab {
+                self.theme.tab
+            } else {
+                inactive
+            };
+            x = frame.print(x, 0, &format!(" {name}{dirty} "), style);
+        }
+        frame.fill(x, 0, inactive);
+    }
+}
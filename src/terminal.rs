@@ -7,13 +7,8 @@ pub struct WindowSize {
 }
 
 pub fn resize_terminal() -> WindowSize {
-    if let Ok((height, width)) = size() {
-        let dimensions = WindowSize {
-            rows: width,
-            columns: height,
-        };
-
-        return dimensions;
+    if let Ok((columns, rows)) = size() {
+        WindowSize { rows, columns }
     } else {
         panic!("could not get terminal size");
     }
@@ -0,0 +1,302 @@
+use ropey::Rope;
+use std::io::{self, Read};
+use tree_sitter::{InputEdit, Point};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Number of columns in `text`, counted in grapheme clusters.
+pub fn grapheme_len(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+/// Byte offset of column `col` in `line`, or the length of `line` when `col`
+/// is past its end.
+pub fn col_to_byte(line: &str, col: usize) -> usize {
+    line.grapheme_indices(true)
+        .nth(col)
+        .map_or(line.len(), |(i, _)| i)
+}
+
+/// Column of byte offset `byte` in `line`.
+pub fn byte_to_col(line: &str, byte: usize) -> usize {
+    grapheme_len(&line[..byte])
+}
+
+/// Line oriented text storage backed by a rope.
+///
+/// Every row is kept terminated by a `'\n'`, so row `n` always maps to
+/// `Rope::line(n)` and an empty buffer has no rows at all. Columns are
+/// expressed in grapheme clusters, so a cursor never lands inside an accented
+/// letter or an emoji sequence.
+#[derive(Debug, Default)]
+pub struct TextBuffer {
+    text: Rope,
+    edits: Vec<Edit>,
+    /// First row changed since the last `take_changed`.
+    changed: Option<usize>,
+    /// Changes not yet handed to the syntax tree.
+    tree_edits: Vec<InputEdit>,
+}
+
+/// A single primitive change to the buffer, addressed by char index into the
+/// whole text. Every mutation of a `TextBuffer` is recorded as one of these so
+/// the undo history can replay or revert it.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    Insert { at: usize, text: String },
+    Remove { at: usize, text: String },
+}
+
+impl Edit {
+    pub fn invert(&self) -> Edit {
+        match self {
+            Edit::Insert { at, text } => Edit::Remove {
+                at: *at,
+                text: text.clone(),
+            },
+            Edit::Remove { at, text } => Edit::Insert {
+                at: *at,
+                text: text.clone(),
+            },
+        }
+    }
+}
+
+impl TextBuffer {
+    pub fn new() -> Self {
+        Self {
+            text: Rope::new(),
+            edits: Vec::new(),
+            changed: None,
+            tree_edits: Vec::new(),
+        }
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Self> {
+        let mut text = Rope::from_reader(reader)?;
+        let len = text.len_chars();
+        if len > 0 && text.char(len - 1) != '\n' {
+            text.insert_char(len, '\n');
+        }
+
+        Ok(Self {
+            text,
+            edits: Vec::new(),
+            changed: None,
+            tree_edits: Vec::new(),
+        })
+    }
+
+    /// Number of rows in the buffer.
+    pub fn len(&self) -> usize {
+        self.text.len_lines() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Contents of row `idx` without the trailing newline.
+    pub fn line(&self, idx: usize) -> String {
+        let line = self.text.line(idx);
+        line.slice(..line.len_chars().saturating_sub(1)).to_string()
+    }
+
+    /// Length of row `idx` in columns, not counting the trailing newline.
+    pub fn line_len(&self, idx: usize) -> usize {
+        grapheme_len(&self.line(idx))
+    }
+
+    /// Row and byte column of byte offset `byte`.
+    fn point(&self, byte: usize) -> Point {
+        let row = self.text.byte_to_line(byte);
+        Point::new(row, byte - self.text.line_to_byte(row))
+    }
+
+    /// Notes that `removed` chars at char index `at` are about to be replaced
+    /// with `inserted`.
+    fn mark_changed(&mut self, at: usize, removed: usize, inserted: &str) {
+        let start_byte = self.text.char_to_byte(at);
+        let old_end_byte = self.text.char_to_byte(at + removed);
+        let start_position = self.point(start_byte);
+        let new_end_position = match inserted.rfind('\n') {
+            Some(idx) => Point::new(
+                start_position.row + inserted.matches('\n').count(),
+                inserted.len() - idx - 1,
+            ),
+            None => Point::new(start_position.row, start_position.column + inserted.len()),
+        };
+        self.tree_edits.push(InputEdit {
+            start_byte,
+            old_end_byte,
+            new_end_byte: start_byte + inserted.len(),
+            start_position,
+            old_end_position: self.point(old_end_byte),
+            new_end_position,
+        });
+
+        let row = start_position.row;
+        self.changed = Some(self.changed.map_or(row, |changed| changed.min(row)));
+    }
+
+    /// Hands over the first row changed since the last call, if any.
+    pub fn take_changed(&mut self) -> Option<usize> {
+        self.changed.take()
+    }
+
+    /// Hands over every change made since the last call, for a syntax tree
+    /// to catch up with.
+    pub fn take_tree_edits(&mut self) -> Vec<InputEdit> {
+        std::mem::take(&mut self.tree_edits)
+    }
+
+    /// Byte offset of the start of row `idx`.
+    pub fn line_to_byte(&self, idx: usize) -> usize {
+        self.text.line_to_byte(idx)
+    }
+
+    /// The text from byte offset `byte` to the end of the chunk of the rope
+    /// holding it, or nothing past the end of the text.
+    pub fn chunk_at_byte(&self, byte: usize) -> &[u8] {
+        if byte >= self.text.len_bytes() {
+            return &[];
+        }
+        let (chunk, start, _, _) = self.text.chunk_at_byte(byte);
+        &chunk.as_bytes()[byte - start..]
+    }
+
+    /// The text between two byte offsets, in pieces.
+    pub fn byte_chunks(&self, start: usize, end: usize) -> impl Iterator<Item = &[u8]> {
+        self.text.byte_slice(start..end).chunks().map(str::as_bytes)
+    }
+
+    fn insert(&mut self, at: usize, text: &str) {
+        self.mark_changed(at, 0, text);
+        self.text.insert(at, text);
+        self.edits.push(Edit::Insert {
+            at,
+            text: text.to_string(),
+        });
+    }
+
+    fn remove(&mut self, start: usize, end: usize) {
+        self.mark_changed(start, end - start, "");
+        let text = self.text.slice(start..end).to_string();
+        self.text.remove(start..end);
+        self.edits.push(Edit::Remove { at: start, text });
+    }
+
+    /// Applies `edit` without recording it, used when walking the undo history.
+    pub fn apply(&mut self, edit: &Edit) {
+        match edit {
+            Edit::Insert { at, text } => self.mark_changed(*at, 0, text),
+            Edit::Remove { at, text } => self.mark_changed(*at, text.chars().count(), ""),
+        }
+        match edit {
+            Edit::Insert { at, text } => self.text.insert(*at, text),
+            Edit::Remove { at, text } => self.text.remove(*at..*at + text.chars().count()),
+        }
+    }
+
+    pub fn has_pending_edits(&self) -> bool {
+        !self.edits.is_empty()
+    }
+
+    /// Hands over every edit made since the last call.
+    pub fn take_edits(&mut self) -> Vec<Edit> {
+        std::mem::take(&mut self.edits)
+    }
+
+    fn char_idx(&self, line: usize, col: usize) -> usize {
+        let row = self.line(line);
+        let chars = row[..col_to_byte(&row, col)].chars().count();
+        self.text.line_to_char(line) + chars
+    }
+
+    /// Char index of `(line, col)`, where `line == len()` means the very end of
+    /// the text.
+    fn pos_idx(&self, line: usize, col: usize) -> usize {
+        if line >= self.len() {
+            self.text.len_chars()
+        } else {
+            self.char_idx(line, col)
+        }
+    }
+
+    /// Text between two `(line, col)` positions, the second one exclusive.
+    /// Passing `(line + 1, 0)` as the end includes the newline of `line`.
+    pub fn text_between(&self, from: (usize, usize), to: (usize, usize)) -> String {
+        let start = self.pos_idx(from.0, from.1);
+        let end = self.pos_idx(to.0, to.1).max(start);
+        self.text.slice(start..end).to_string()
+    }
+
+    /// Removes the text between two positions like `text_between` returns it,
+    /// except that the newline ending the last row is always kept.
+    pub fn remove_between(&mut self, from: (usize, usize), to: (usize, usize)) {
+        let start = self.pos_idx(from.0, from.1);
+        let end = self
+            .pos_idx(to.0, to.1)
+            .min(self.text.len_chars().saturating_sub(1));
+        if start < end {
+            self.remove(start, end);
+        }
+    }
+
+    /// Inserts `text` at `(line, col)`; newlines in `text` create new rows.
+    /// Inserting at `line == len()` must end `text` with a newline.
+    pub fn insert_str(&mut self, line: usize, col: usize, text: &str) {
+        let idx = self.pos_idx(line, col);
+        self.insert(idx, text);
+    }
+
+    pub fn insert_char(&mut self, line: usize, col: usize, c: char) {
+        let idx = self.char_idx(line, col);
+        self.insert(idx, c.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Removes the grapheme cluster at `col`, leaving the row's newline
+    /// untouched.
+    pub fn delete_char(&mut self, line: usize, col: usize) {
+        if col < self.line_len(line) {
+            let start = self.char_idx(line, col);
+            let end = self.char_idx(line, col + 1);
+            self.remove(start, end);
+        }
+    }
+
+    /// Inserts a new row before row `idx`. `idx == len()` appends.
+    pub fn insert_line(&mut self, idx: usize, chars: &str) {
+        let at = self.text.line_to_char(idx);
+        self.insert(at, &format!("{chars}\n"));
+    }
+
+    /// Removes rows `start..end`.
+    pub fn remove_lines(&mut self, start: usize, end: usize) {
+        let start = self.text.line_to_char(start);
+        let end = self.text.line_to_char(end);
+        self.remove(start, end);
+    }
+
+    /// Breaks row `line` in two at `col`.
+    pub fn split_line(&mut self, line: usize, col: usize) {
+        let idx = self.char_idx(line, col);
+        self.insert(idx, "\n");
+    }
+
+    /// Appends row `line + 1` onto the end of row `line`.
+    pub fn join_lines(&mut self, line: usize) {
+        if line + 1 < self.len() {
+            let idx = self.text.line_to_char(line + 1) - 1;
+            self.remove(idx, idx + 1);
+        }
+    }
+}
+
+impl std::fmt::Display for TextBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for chunk in self.text.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::BTreeMap;
+
+use crossterm::style::Color;
+use serde::Deserialize;
+
+use crate::config;
+use crate::editor::{EditorState, Highlight};
+use crate::screen::Style;
+
+/// How many colors the terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+/// The 16 named colors in palette order, with xterm's default values.
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (205, 0, 0)),
+    (Color::DarkGreen, (0, 205, 0)),
+    (Color::DarkYellow, (205, 205, 0)),
+    (Color::DarkBlue, (0, 0, 238)),
+    (Color::DarkMagenta, (205, 0, 205)),
+    (Color::DarkCyan, (0, 205, 205)),
+    (Color::Grey, (229, 229, 229)),
+    (Color::DarkGrey, (127, 127, 127)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (92, 92, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// Names of the colors in `ANSI16`, as written in theme files.
+const NAMES: [&str; 16] = [
+    "black",
+    "darkred",
+    "darkgreen",
+    "darkyellow",
+    "darkblue",
+    "darkmagenta",
+    "darkcyan",
+    "grey",
+    "darkgrey",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+];
+
+/// Levels of each channel in the 6x6x6 color cube of the 256 color palette.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+/// The value of `color`, unless it is the terminal's default.
+fn rgb(color: Color) -> Option<(u8, u8, u8)> {
+    match color {
+        Color::Rgb { r, g, b } => Some((r, g, b)),
+        Color::AnsiValue(n) if n < 16 => Some(ANSI16[n as usize].1),
+        Color::AnsiValue(n) if n < 232 => {
+            let n = n - 16;
+            let level = |i: u8| CUBE[i as usize];
+            Some((level(n / 36), level(n / 6 % 6), level(n % 6)))
+        }
+        Color::AnsiValue(n) => {
+            let grey = 8 + 10 * (n - 232);
+            Some((grey, grey, grey))
+        }
+        Color::Reset => None,
+        named => ANSI16
+            .iter()
+            .find(|(c, _)| *c == named)
+            .map(|(_, rgb)| *rgb),
+    }
+}
+
+/// The closest entry of the 256 color palette to `rgb`, past the 16 that
+/// terminals let users redefine.
+fn to_ansi256(rgb: (u8, u8, u8)) -> u8 {
+    let nearest = |v: u8| {
+        (0..6)
+            .min_by_key(|i| (CUBE[*i] as i32 - v as i32).abs())
+            .unwrap_or(0)
+    };
+    let (r, g, b) = (nearest(rgb.0), nearest(rgb.1), nearest(rgb.2));
+    let cube = (CUBE[r], CUBE[g], CUBE[b]);
+
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let step = (average.saturating_sub(8) / 10).min(23) as u8;
+    let grey = 8 + 10 * step;
+
+    if distance(rgb, (grey, grey, grey)) < distance(rgb, cube) {
+        232 + step
+    } else {
+        16 + 36 * r as u8 + 6 * g as u8 + b as u8
+    }
+}
+
+impl ColorDepth {
+    /// Guesses what the terminal supports from `COLORTERM` and `TERM`.
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if matches!(colorterm.as_str(), "truecolor" | "24bit") || term.ends_with("-direct") {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+
+    /// The closest color to `color` the terminal can show.
+    fn convert(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorDepth::TrueColor, _) | (_, Color::Reset) => color,
+            (ColorDepth::Ansi256, Color::Rgb { r, g, b }) => {
+                Color::AnsiValue(to_ansi256((r, g, b)))
+            }
+            (ColorDepth::Ansi256, _) => color,
+            (ColorDepth::Ansi16, Color::Rgb { .. } | Color::AnsiValue(_)) => match rgb(color) {
+                Some(rgb) => ANSI16
+                    .iter()
+                    .min_by_key(|(_, value)| distance(rgb, *value))
+                    .map_or(color, |(named, _)| *named),
+                None => color,
+            },
+            (ColorDepth::Ansi16, _) => color,
+        }
+    }
+
+    fn convert_style(self, style: Style) -> Style {
+        Style {
+            fg: style.fg.map(|c| self.convert(c)),
+            bg: style.bg.map(|c| self.convert(c)),
+            reverse: style.reverse,
+        }
+    }
+}
+
+/// Parses `#rrggbb`, a palette index from 0 to 255, `default`, or a color
+/// name such as `dark_blue` or `darkgray`.
+fn parse_color(s: &str) -> Result<Color, String> {
+    let name = s
+        .to_lowercase()
+        .replace(['_', '-'], "")
+        .replace("gray", "grey");
+    if let Some(hex) = s.strip_prefix('#') {
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+        };
+        if let (6, Some(r), Some(g), Some(b)) = (hex.len(), channel(0), channel(2), channel(4)) {
+            return Ok(Color::Rgb { r, g, b });
+        }
+    } else if let Ok(n) = s.parse::<u8>() {
+        return Ok(Color::AnsiValue(n));
+    } else if name == "default" {
+        return Ok(Color::Reset);
+    } else if let Some(idx) = NAMES.iter().position(|n| *n == name) {
+        return Ok(ANSI16[idx].0);
+    }
+    Err(format!("Invalid color: {s}"))
+}
+
+/// A scope as written in a theme file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StyleSpec {
+    fg: Option<String>,
+    bg: Option<String>,
+    #[serde(default)]
+    reverse: bool,
+}
+
+impl StyleSpec {
+    fn style(&self) -> Result<Style, String> {
+        Ok(Style {
+            fg: self.fg.as_deref().map(parse_color).transpose()?,
+            bg: self.bg.as_deref().map(parse_color).transpose()?,
+            reverse: self.reverse,
+        })
+    }
+}
+
+/// Names of the `Theme` fields, as used in theme files.
+const SCOPES: [&str; 17] = [
+    "normal",
+    "comment",
+    "keyword",
+    "type",
+    "string",
+    "number",
+    "function",
+    "selection",
+    "search_match",
+    "non_text",
+    "line_number",
+    "line_number_current",
+    "status_line",
+    "status_line_inactive",
+    "separator",
+    "tab",
+    "tab_inactive",
+];
+
+/// The style of each part of the screen.
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub name: String,
+    /// Text without any other highlight, and the background of the text
+    /// area.
+    pub normal: Style,
+    pub comment: Style,
+    pub keyword: Style,
+    pub r#type: Style,
+    pub string: Style,
+    pub number: Style,
+    pub function: Style,
+    pub selection: Style,
+    pub search_match: Style,
+    /// The `~` past the end of the buffer and the welcome text.
+    pub non_text: Style,
+    pub line_number: Style,
+    /// The line number of the cursor row.
+    pub line_number_current: Style,
+    /// The status line of the current window, and the bottom line.
+    pub status_line: Style,
+    pub status_line_inactive: Style,
+    /// The line between windows side by side.
+    pub separator: Style,
+    pub tab: Style,
+    pub tab_inactive: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            name: String::from("default"),
+            normal: Style::default(),
+            comment: Style::fg(Color::DarkCyan),
+            keyword: Style::fg(Color::Yellow),
+            r#type: Style::fg(Color::Green),
+            string: Style::fg(Color::Magenta),
+            number: Style::fg(Color::Red),
+            function: Style::fg(Color::Blue),
+            selection: Style {
+                reverse: true,
+                ..Style::default()
+            },
+            search_match: Style::new(Color::Black, Color::Yellow),
+            non_text: Style::default(),
+            line_number: Style::fg(Color::DarkGrey),
+            line_number_current: Style::fg(Color::Yellow),
+            status_line: Style::new(Color::Black, Color::White),
+            status_line_inactive: Style::new(Color::White, Color::DarkGrey),
+            separator: Style::default(),
+            tab: Style::new(Color::Black, Color::White),
+            tab_inactive: Style::new(Color::White, Color::DarkGrey),
+        }
+    }
+}
+
+impl Theme {
+    fn scope_mut(&mut self, name: &str) -> Option<&mut Style> {
+        let style = match name {
+            "normal" => &mut self.normal,
+            "comment" => &mut self.comment,
+            "keyword" => &mut self.keyword,
+            "type" => &mut self.r#type,
+            "string" => &mut self.string,
+            "number" => &mut self.number,
+            "function" => &mut self.function,
+            "selection" => &mut self.selection,
+            "search_match" => &mut self.search_match,
+            "non_text" => &mut self.non_text,
+            "line_number" => &mut self.line_number,
+            "line_number_current" => &mut self.line_number_current,
+            "status_line" => &mut self.status_line,
+            "status_line_inactive" => &mut self.status_line_inactive,
+            "separator" => &mut self.separator,
+            "tab" => &mut self.tab,
+            "tab_inactive" => &mut self.tab_inactive,
+            _ => return None,
+        };
+        Some(style)
+    }
+
+    /// Loads `themes/<name>.toml` from the config folder over the default
+    /// theme, with its colors brought down to what the terminal can show.
+    /// `default` is the built in theme.
+    pub fn load(name: &str) -> Result<Self, String> {
+        let mut theme = Self {
+            name: name.to_string(),
+            ..Self::default()
+        };
+
+        if name != "default" {
+            let path = config::config_dir()
+                .map(|dir| dir.join("themes").join(format!("{name}.toml")))
+                .filter(|path| path.is_file())
+                .ok_or_else(|| format!("Theme not found: {name}"))?;
+            let table = config::read_table(&path)?;
+            let scopes = BTreeMap::<String, StyleSpec>::deserialize(table)
+                .map_err(|e| format!("{}: {}", path.display(), e.message()))?;
+            for (scope, spec) in scopes {
+                let style = spec
+                    .style()
+                    .map_err(|e| format!("{}: {scope}: {e}", path.display()))?;
+                *theme
+                    .scope_mut(&scope)
+                    .ok_or_else(|| format!("{}: Unknown scope: {scope}", path.display()))? = style;
+            }
+        }
+
+        let depth = ColorDepth::detect();
+        for scope in SCOPES {
+            if let Some(style) = theme.scope_mut(scope) {
+                *style = depth.convert_style(*style);
+            }
+        }
+        // Parts drawn over the text area keep its colors unless they set
+        // their own.
+        let normal = theme.normal;
+        for style in [
+            &mut theme.non_text,
+            &mut theme.separator,
+            &mut theme.line_number,
+            &mut theme.line_number_current,
+        ] {
+            *style = style.or(normal);
+        }
+        Ok(theme)
+    }
+
+    /// How text with highlight `hl` is drawn. Colors it leaves unset come
+    /// from `normal`.
+    pub fn highlight(&self, hl: Highlight) -> Style {
+        let style = match hl {
+            Highlight::Normal => self.normal,
+            Highlight::Comment => self.comment,
+            Highlight::Keyword => self.keyword,
+            Highlight::Type => self.r#type,
+            Highlight::String => self.string,
+            Highlight::Number => self.number,
+            Highlight::Function => self.function,
+            Highlight::Match => self.search_match,
+            Highlight::Selection => self.selection,
+        };
+        style.or(self.normal)
+    }
+}
+
+impl EditorState {
+    /// Switches to the theme named by the `theme` option, going back to the
+    /// previous one if it does not load.
+    pub fn editor_load_theme(&mut self) {
+        match Theme::load(&self.config.theme) {
+            Ok(theme) => self.theme = theme,
+            Err(msg) => {
+                self.config.theme = self.theme.name.clone();
+                self.message = Some(msg);
+            }
+        }
+    }
+}
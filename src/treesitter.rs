@@ -0,0 +1,338 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{Language, Node, Parser, Point, Query, QueryCursor, Tree};
+use tree_sitter_language::LanguageFn;
+
+use crate::editor::{EditorMode, EditorState, Highlight};
+use crate::textbuffer::{byte_to_col, col_to_byte, TextBuffer};
+use crate::undo::Cursor;
+
+/// A bundled tree-sitter grammar.
+struct Grammar {
+    extensions: &'static [&'static str],
+    language: LanguageFn,
+    highlights: &'static str,
+    /// Node kinds `]m` and `[m` jump between.
+    items: &'static [&'static str],
+}
+
+const GRAMMARS: [Grammar; 4] = [
+    Grammar {
+        extensions: &["rs"],
+        language: tree_sitter_rust::LANGUAGE,
+        highlights: tree_sitter_rust::HIGHLIGHTS_QUERY,
+        items: &["function_item", "function_signature_item"],
+    },
+    Grammar {
+        extensions: &["toml"],
+        language: tree_sitter_toml_ng::LANGUAGE,
+        highlights: tree_sitter_toml_ng::HIGHLIGHTS_QUERY,
+        items: &["table", "table_array_element"],
+    },
+    Grammar {
+        extensions: &["md", "markdown"],
+        language: tree_sitter_md::LANGUAGE,
+        highlights: tree_sitter_md::HIGHLIGHT_QUERY_BLOCK,
+        items: &["atx_heading", "setext_heading"],
+    },
+    Grammar {
+        extensions: &["json"],
+        language: tree_sitter_json::LANGUAGE,
+        highlights: tree_sitter_json::HIGHLIGHTS_QUERY,
+        items: &["pair"],
+    },
+];
+
+/// How text captured as `name` by a highlights query is drawn, if at all.
+fn capture_highlight(name: &str) -> Option<Highlight> {
+    let scope = name.split('.').next().unwrap_or(name);
+    let highlight = match scope {
+        "none" => Highlight::Normal,
+        "comment" => Highlight::Comment,
+        "keyword" | "attribute" | "label" => Highlight::Keyword,
+        "type" | "constructor" => Highlight::Type,
+        "string" | "escape" => Highlight::String,
+        "number" | "boolean" | "constant" => Highlight::Number,
+        "function" => Highlight::Function,
+        "text" if name == "text.title" => Highlight::Keyword,
+        "text" => Highlight::String,
+        "punctuation" if name == "punctuation.special" => Highlight::Keyword,
+        _ => return None,
+    };
+    Some(highlight)
+}
+
+/// A buffer's syntax tree, kept up to date with its edits.
+pub struct SyntaxTree {
+    grammar: &'static Grammar,
+    parser: Parser,
+    tree: Option<Tree>,
+    query: Query,
+    /// Highlight of each capture of `query`.
+    captures: Vec<Option<Highlight>>,
+}
+
+impl fmt::Debug for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyntaxTree")
+            .field("tree", &self.tree)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SyntaxTree {
+    /// A parser for `path`, if one of the bundled grammars covers it.
+    pub fn for_path(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?;
+        let grammar = GRAMMARS
+            .iter()
+            .find(|grammar| grammar.extensions.contains(&ext))?;
+
+        let language = Language::new(grammar.language);
+        let mut parser = Parser::new();
+        parser.set_language(&language).ok()?;
+        let query = Query::new(&language, grammar.highlights).ok()?;
+        let captures = query
+            .capture_names()
+            .iter()
+            .map(|name| capture_highlight(name))
+            .collect();
+
+        Some(Self {
+            grammar,
+            parser,
+            tree: None,
+            query,
+            captures,
+        })
+    }
+
+    /// Brings the tree up to date with the edits made to `text`. Returns the
+    /// first row whose syntax may have changed, if any.
+    pub fn parse(&mut self, text: &mut TextBuffer) -> Option<usize> {
+        let edits = text.take_tree_edits();
+        if self.tree.is_some() && edits.is_empty() {
+            return None;
+        }
+
+        let mut old = self.tree.take();
+        if let Some(old) = &mut old {
+            for edit in &edits {
+                old.edit(edit);
+            }
+        }
+        let mut input = |byte: usize, _: Point| text.chunk_at_byte(byte);
+        self.tree = self
+            .parser
+            .parse_with_options(&mut input, old.as_ref(), None);
+
+        let (old, new) = (old?, self.tree.as_ref()?);
+        let edited = edits.iter().map(|edit| edit.start_position.row);
+        let changed = old.changed_ranges(new).map(|range| range.start_point.row);
+        edited.chain(changed).min()
+    }
+
+    pub fn is_parsed(&self) -> bool {
+        self.tree.is_some()
+    }
+
+    fn root(&self) -> Option<Node<'_>> {
+        self.tree.as_ref().map(Tree::root_node)
+    }
+
+    /// Highlighting of each row in `rows`, one entry per byte.
+    pub fn highlight(&self, text: &TextBuffer, rows: Range<usize>) -> Vec<Vec<Highlight>> {
+        let mut hl: Vec<Vec<Highlight>> = rows
+            .clone()
+            .map(|row| vec![Highlight::Normal; text.line(row).len()])
+            .collect();
+        let Some(root) = self.root() else {
+            return hl;
+        };
+
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(text.line_to_byte(rows.start)..text.line_to_byte(rows.end));
+        let source = |node: Node| text.byte_chunks(node.start_byte(), node.end_byte());
+        let mut captures = cursor.captures(&self.query, root, source);
+        while let Some((m, idx)) = captures.next() {
+            let capture = m.captures[*idx];
+            let Some(highlight) = self.captures[capture.index as usize] else {
+                continue;
+            };
+            let (start, end) = (capture.node.start_position(), capture.node.end_position());
+            for row in start.row.max(rows.start)..=end.row.min(rows.end - 1) {
+                let line = &mut hl[row - rows.start];
+                let len = line.len();
+                let from = if row == start.row { start.column } else { 0 };
+                let to = if row == end.row { end.column } else { len };
+                line[from.min(len)..to.min(len)].fill(highlight);
+            }
+        }
+        hl
+    }
+
+    /// Where the items `]m` and `[m` jump between start, in order.
+    fn item_starts(&self) -> Vec<Point> {
+        let mut starts = Vec::new();
+        let Some(root) = self.root() else {
+            return starts;
+        };
+
+        let mut cursor = root.walk();
+        'walk: loop {
+            if self.grammar.items.contains(&cursor.node().kind()) {
+                starts.push(cursor.node().start_position());
+            }
+            if cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+        starts
+    }
+}
+
+/// A selection grown to a syntax node.
+#[derive(Debug)]
+pub struct Expansion {
+    /// Mode before, where NORMAL means there was no selection.
+    mode: EditorMode,
+    /// `(anchor, cursor)` before.
+    before: (Cursor, Cursor),
+    /// `(anchor, cursor)` after.
+    after: (Cursor, Cursor),
+}
+
+/// Byte offset of `(cx, cy)` in `text`.
+fn cursor_to_byte(text: &TextBuffer, (cx, cy): Cursor) -> usize {
+    text.line_to_byte(cy) + col_to_byte(&text.line(cy), cx)
+}
+
+/// The cursor position at row and byte column `point`.
+fn point_to_cursor(text: &TextBuffer, point: Point) -> Cursor {
+    let row = point.row.min(text.len().saturating_sub(1));
+    (byte_to_col(&text.line(row), point.column), row)
+}
+
+impl EditorState {
+    /// Brings the current buffer's syntax tree up to date. Returns false when
+    /// the buffer has none.
+    fn editor_sync_syntax(&mut self) -> bool {
+        let buffer = &mut self.buffer;
+        buffer.syntax.sync(&mut buffer.row);
+        let parsed = self.buffer.syntax.tree().is_some() && !self.buffer.row.is_empty();
+        if !parsed {
+            self.message = Some(String::from("No syntax tree for this buffer"));
+        }
+        parsed
+    }
+
+    /// Selects the syntax node under the cursor, or grows the selection to
+    /// the node around it.
+    pub fn editor_expand_selection(&mut self) {
+        if !self.editor_sync_syntax() {
+            return;
+        }
+        let visual = self.mode == EditorMode::VISUAL;
+        let current = if visual {
+            (self.anchor, (self.cx, self.cy))
+        } else {
+            ((self.cx, self.cy), (self.cx, self.cy))
+        };
+        let Some(root) = self.buffer.syntax.tree().and_then(SyntaxTree::root) else {
+            return;
+        };
+
+        let text = &self.buffer.row;
+        let (anchor, cursor) = current;
+        let (from, to) = if (anchor.1, anchor.0) <= (cursor.1, cursor.0) {
+            current
+        } else {
+            (current.1, current.0)
+        };
+        let start = cursor_to_byte(text, from);
+        let end = cursor_to_byte(text, (to.0 + 1, to.1));
+        let Some(mut node) = root.named_descendant_for_byte_range(start, end) else {
+            return;
+        };
+        // A node selected already gives way to the one around it.
+        while visual && node.byte_range() == (start..end) {
+            match node.parent() {
+                Some(parent) => node = parent,
+                None => return,
+            }
+        }
+
+        let anchor = point_to_cursor(text, node.start_position());
+        let end = node.end_position();
+        // The selection is inclusive, so it stops on the char before the end.
+        let cursor = if end.column == 0 && end.row > 0 {
+            let row = (end.row - 1).min(text.len() - 1);
+            (text.line_len(row).saturating_sub(1), row)
+        } else {
+            let (cx, cy) = point_to_cursor(text, end);
+            (cx.saturating_sub(1), cy)
+        };
+
+        if !visual {
+            self.expansions.clear();
+        }
+        self.expansions.push(Expansion {
+            mode: self.mode,
+            before: current,
+            after: (anchor, cursor),
+        });
+        self.mode = EditorMode::VISUAL;
+        self.anchor = anchor;
+        (self.cx, self.cy) = cursor;
+    }
+
+    /// Goes back to the selection the last `editor_expand_selection` grew,
+    /// or to NORMAL mode when it started there.
+    pub fn editor_shrink_selection(&mut self) {
+        let current = (self.anchor, (self.cx, self.cy));
+        match self.expansions.pop() {
+            Some(expansion) if expansion.after == current => {
+                self.mode = expansion.mode;
+                (self.anchor, (self.cx, self.cy)) = expansion.before;
+            }
+            _ => self.expansions.clear(),
+        }
+    }
+
+    /// `]m` and `[m`: moves to the start of the `count`th next or previous
+    /// item, such as a function in Rust or a heading in Markdown.
+    pub fn editor_goto_item(&mut self, forward: bool, count: usize) {
+        if !self.editor_sync_syntax() {
+            return;
+        }
+        let starts = self
+            .buffer
+            .syntax
+            .tree()
+            .map(SyntaxTree::item_starts)
+            .unwrap_or_default();
+
+        let text = &self.buffer.row;
+        let at = Point::new(self.cy, col_to_byte(&text.line(self.cy), self.cx));
+        let target = if forward {
+            starts.iter().filter(|start| **start > at).nth(count - 1)
+        } else {
+            starts
+                .iter()
+                .rev()
+                .filter(|start| **start < at)
+                .nth(count - 1)
+        };
+        if let Some(start) = target {
+            (self.cx, self.cy) = point_to_cursor(text, *start);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::textbuffer::{Edit, TextBuffer};
+
+/// Cursor position as `(cx, cy)`.
+pub type Cursor = (usize, usize);
+
+#[derive(Debug)]
+struct UndoNode {
+    parent: usize,
+    // The child redo should follow, i.e. the branch that was most recently
+    // created or undone out of.
+    last_child: Option<usize>,
+    edits: Vec<Edit>,
+    cursor_before: Cursor,
+    cursor_after: Cursor,
+}
+
+/// Undo history stored as a tree, so undoing and then making a new change
+/// starts a new branch instead of throwing the old one away.
+///
+/// Node 0 is the root and represents the buffer before any change was made.
+#[derive(Debug)]
+pub struct UndoTree {
+    nodes: Vec<UndoNode>,
+    current: usize,
+    /// The node matching the file on disk, if any.
+    saved: Option<usize>,
+}
+
+impl UndoTree {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![UndoNode {
+                parent: 0,
+                last_child: None,
+                edits: Vec::new(),
+                cursor_before: (0, 0),
+                cursor_after: (0, 0),
+            }],
+            current: 0,
+            saved: Some(0),
+        }
+    }
+
+    /// Remembers the current node as the one written to disk.
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.current);
+    }
+
+    /// Whether the buffer is back to how it was last written.
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.current)
+    }
+
+    /// Records a group of edits as a single undo step below the current node.
+    pub fn record(&mut self, edits: Vec<Edit>, cursor_before: Cursor, cursor_after: Cursor) {
+        if edits.is_empty() {
+            return;
+        }
+
+        let idx = self.nodes.len();
+        self.nodes.push(UndoNode {
+            parent: self.current,
+            last_child: None,
+            edits,
+            cursor_before,
+            cursor_after,
+        });
+        self.nodes[self.current].last_child = Some(idx);
+        self.current = idx;
+    }
+
+    /// Reverts the current step, returning where the cursor was before it.
+    pub fn undo(&mut self, buffer: &mut TextBuffer) -> Option<Cursor> {
+        if self.current == 0 {
+            return None;
+        }
+
+        let node = &self.nodes[self.current];
+        for edit in node.edits.iter().rev() {
+            buffer.apply(&edit.invert());
+        }
+        let cursor = node.cursor_before;
+        let parent = node.parent;
+        self.nodes[parent].last_child = Some(self.current);
+        self.current = parent;
+
+        Some(cursor)
+    }
+
+    /// Replays the most recently undone step, returning the cursor after it.
+    pub fn redo(&mut self, buffer: &mut TextBuffer) -> Option<Cursor> {
+        let child = self.nodes[self.current].last_child?;
+
+        let node = &self.nodes[child];
+        for edit in node.edits.iter() {
+            buffer.apply(edit);
+        }
+        self.current = child;
+
+        Some(node.cursor_after)
+    }
+}
@@ -0,0 +1,540 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::editor::EditorState;
+use crate::screen::Frame;
+use crate::undo::Cursor;
+
+/// A rectangle of terminal cells.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+/// A viewport onto a buffer. The window being edited keeps its cursor and
+/// scroll offsets in `EditorState`, so `cursor` and `offset` are only up to
+/// date for the other windows.
+#[derive(Debug)]
+pub struct Window {
+    pub id: usize,
+    pub buffer: usize,
+    pub cursor: Cursor,
+    /// `(rowoff, coloff)`
+    pub offset: (usize, usize),
+    /// Where the window is drawn, including its status line.
+    pub rect: Rect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitKind {
+    /// Windows above one another, made by `:split`.
+    Horizontal,
+    /// Windows side by side, made by `:vsplit`.
+    Vertical,
+}
+
+/// How the screen is divided between windows.
+#[derive(Debug)]
+pub enum Layout {
+    Window(usize),
+    Split {
+        kind: SplitKind,
+        /// Rows or columns given to `first`, or `None` to share the space
+        /// evenly between the windows.
+        size: Option<u16>,
+        /// What `first` got the last time the layout was arranged.
+        shown: u16,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    /// Number of windows lined up along `kind`.
+    fn count(&self, kind: SplitKind) -> u16 {
+        match self {
+            Layout::Window(_) => 1,
+            Layout::Split {
+                kind: split,
+                first,
+                second,
+                ..
+            } if *split == kind => first.count(kind) + second.count(kind),
+            Layout::Split { first, second, .. } => first.count(kind).max(second.count(kind)),
+        }
+    }
+
+    /// Splits window `target`, putting window `new` above or left of it.
+    pub fn split(&mut self, target: usize, new: usize, kind: SplitKind) -> bool {
+        match self {
+            Layout::Window(id) if *id == target => {
+                *self = Layout::Split {
+                    kind,
+                    size: None,
+                    shown: 0,
+                    first: Box::new(Layout::Window(new)),
+                    second: Box::new(Layout::Window(target)),
+                };
+                true
+            }
+            Layout::Window(_) => false,
+            Layout::Split { first, second, .. } => {
+                first.split(target, new, kind) || second.split(target, new, kind)
+            }
+        }
+    }
+
+    /// Removes window `id`, giving its space to its neighbour.
+    pub fn remove(&mut self, id: usize) -> bool {
+        let Layout::Split { first, second, .. } = self else {
+            return false;
+        };
+
+        if matches!(**first, Layout::Window(w) if w == id) {
+            *self = std::mem::replace(second.as_mut(), Layout::Window(0));
+            true
+        } else if matches!(**second, Layout::Window(w) if w == id) {
+            *self = std::mem::replace(first.as_mut(), Layout::Window(0));
+            true
+        } else {
+            first.remove(id) || second.remove(id)
+        }
+    }
+
+    /// Shares the space evenly between every window again.
+    pub fn equalize(&mut self) {
+        if let Layout::Split {
+            size,
+            first,
+            second,
+            ..
+        } = self
+        {
+            *size = None;
+            first.equalize();
+            second.equalize();
+        }
+    }
+
+    /// Grows window `id` by `delta` rows or columns, moving the closest
+    /// border of a `kind` split around it. Returns `None` when `id` is not
+    /// part of this layout, and whether a border was found otherwise.
+    pub fn resize(&mut self, id: usize, kind: SplitKind, delta: i32) -> Option<bool> {
+        match self {
+            Layout::Window(w) => (*w == id).then_some(false),
+            Layout::Split {
+                kind: split,
+                size,
+                shown,
+                first,
+                second,
+            } => {
+                let (done, sign) = match first.resize(id, kind, delta) {
+                    Some(done) => (done, 1),
+                    None => (second.resize(id, kind, delta)?, -1),
+                };
+                if done || *split != kind {
+                    return Some(done);
+                }
+
+                *size = Some((*shown as i32 + sign * delta).max(1) as u16);
+                Some(true)
+            }
+        }
+    }
+
+    /// Lays the windows out over `rect`, in order, into `windows`.
+    pub fn arrange(&mut self, rect: Rect, windows: &mut Vec<(usize, Rect)>) {
+        match self {
+            Layout::Window(id) => windows.push((*id, rect)),
+            Layout::Split {
+                kind,
+                size,
+                shown,
+                first,
+                second,
+            } => {
+                let weight = first.count(*kind);
+                let total_weight = weight + second.count(*kind);
+                match kind {
+                    SplitKind::Horizontal => {
+                        let even =
+                            (rect.height as u32 * weight as u32 / total_weight as u32) as u16;
+                        let height = size
+                            .unwrap_or(even)
+                            .min(rect.height.saturating_sub(1))
+                            .max(1);
+                        *shown = height;
+                        first.arrange(Rect { height, ..rect }, windows);
+                        second.arrange(
+                            Rect {
+                                y: rect.y + height,
+                                height: rect.height.saturating_sub(height),
+                                ..rect
+                            },
+                            windows,
+                        );
+                    }
+                    SplitKind::Vertical => {
+                        // One column goes to the separator between the two.
+                        let available = rect.width.saturating_sub(1);
+                        let even = (available as u32 * weight as u32 / total_weight as u32) as u16;
+                        let width = size.unwrap_or(even).min(available.saturating_sub(1)).max(1);
+                        *shown = width;
+                        first.arrange(Rect { width, ..rect }, windows);
+                        second.arrange(
+                            Rect {
+                                x: rect.x + width + 1,
+                                width: rect.width.saturating_sub(width + 1),
+                                ..rect
+                            },
+                            windows,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl EditorState {
+    /// Whether each window gets a status line of its own, which they only
+    /// do once there is more than one.
+    fn editor_window_status(&self) -> bool {
+        self.windows.len() > 1
+    }
+
+    /// Places every window on the screen.
+    pub fn editor_layout(&mut self) {
+        let tabline = self.editor_tabline_height();
+        let area = Rect {
+            x: 0,
+            y: tabline,
+            width: self.dimensions.columns,
+            height: self.dimensions.rows.saturating_sub(tabline),
+        };
+        let mut rects = Vec::new();
+        self.layout.arrange(area, &mut rects);
+        // Keep the windows in screen order, which Ctrl-W w follows.
+        let mut windows = std::mem::take(&mut self.windows);
+        for (id, rect) in rects {
+            if let Some(idx) = windows.iter().position(|window| window.id == id) {
+                let mut window = windows.remove(idx);
+                window.rect = rect;
+                self.windows.push(window);
+            }
+        }
+    }
+
+    fn editor_window(&self) -> &Window {
+        self.windows
+            .iter()
+            .find(|window| window.id == self.window)
+            .unwrap()
+    }
+
+    /// Screen area the text of the current window is drawn in, past its
+    /// line number gutter.
+    pub fn editor_text_area(&self) -> Rect {
+        let mut rect = self.editor_window().rect;
+        if self.editor_window_status() {
+            rect.height = rect.height.saturating_sub(1);
+        }
+        let gutter = self.editor_gutter_width(self.buffer.row.len(), rect.width) as u16;
+        rect.x += gutter;
+        rect.width -= gutter;
+        rect
+    }
+
+    pub fn editor_store_window(&mut self) {
+        let (cursor, offset, buffer) = (
+            (self.cx, self.cy),
+            (self.rowoff, self.coloff),
+            self.buffer.id,
+        );
+        let id = self.window;
+        if let Some(window) = self.windows.iter_mut().find(|window| window.id == id) {
+            window.cursor = cursor;
+            window.offset = offset;
+            window.buffer = buffer;
+        }
+    }
+
+    /// Makes window `id` current, without saving the view of the window that
+    /// was.
+    pub fn editor_load_window(&mut self, id: usize) {
+        let Some(window) = self.windows.iter().find(|window| window.id == id) else {
+            return;
+        };
+        let (buffer, cursor, offset) = (window.buffer, window.cursor, window.offset);
+
+        self.window = id;
+        self.editor_swap_buffer(buffer);
+        (self.rowoff, self.coloff) = offset;
+        // The buffer may have been edited from another window since.
+        let numrows = self.buffer.row.len();
+        self.cy = cursor.1.min(numrows.saturating_sub(1));
+        self.cx = if numrows == 0 {
+            0
+        } else {
+            cursor.0.min(self.buffer.row.line_len(self.cy))
+        };
+    }
+
+    pub fn editor_focus_window(&mut self, id: usize) {
+        if id == self.window {
+            return;
+        }
+        self.editor_store_window();
+        self.editor_load_window(id);
+    }
+
+    /// Splits the current window in two, optionally opening `file` in the
+    /// new one, which becomes current.
+    pub fn editor_split(&mut self, kind: SplitKind, file: Option<String>) {
+        let text = self.editor_text_area();
+        let too_small = match kind {
+            SplitKind::Horizontal => text.height < 3,
+            SplitKind::Vertical => text.width < 3,
+        };
+        if too_small {
+            self.message = Some(String::from("Not enough room"));
+            return;
+        }
+
+        self.editor_store_window();
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.windows.push(Window {
+            id,
+            buffer: self.buffer.id,
+            cursor: (self.cx, self.cy),
+            offset: (self.rowoff, self.coloff),
+            rect: Rect::default(),
+        });
+        self.layout.split(self.window, id, kind);
+        self.layout.equalize();
+        self.window = id;
+        self.editor_layout();
+
+        if let Some(file) = file {
+            self.editor_open(&file);
+        }
+    }
+
+    /// Closes the current window, or its tab page when it is the last window
+    /// there. Its buffer stays loaded, but leaving unsaved changes out of
+    /// sight needs `force`, unless another window shows the buffer too.
+    pub fn editor_close_window(&mut self, force: bool) {
+        if self.windows.len() == 1 {
+            if self.editor_has_other_windows() {
+                return self.editor_close_tab(force);
+            }
+            self.message = Some(String::from("Cannot close last window"));
+            return;
+        }
+        if !self.editor_buffer_shown_elsewhere() && !self.editor_can_abandon(force) {
+            return;
+        }
+
+        let idx = self
+            .windows
+            .iter()
+            .position(|window| window.id == self.window)
+            .unwrap();
+        self.layout.remove(self.window);
+        self.layout.equalize();
+        self.editor_store_view();
+        self.windows.remove(idx);
+        let next = self.windows[idx.saturating_sub(1)].id;
+        self.editor_load_window(next);
+        self.editor_layout();
+    }
+
+    /// Closes every window but the current one.
+    pub fn editor_only_window(&mut self) {
+        self.windows.retain(|window| window.id == self.window);
+        self.layout = Layout::Window(self.window);
+        self.editor_layout();
+    }
+
+    /// The window next to the current one in `direction` (one of `hjkl`),
+    /// preferring the one level with the cursor.
+    fn editor_neighbor_window(&self, direction: char) -> Option<usize> {
+        let rect = self.editor_window().rect;
+        let text = self.editor_text_area();
+        let (column, line) = self.editor_cursor_cell();
+        let row = text.y + (line as u16).min(text.height.saturating_sub(1));
+        let col = text.x + (column as u16).min(text.width.saturating_sub(1));
+
+        let candidates = self.windows.iter().filter(|window| {
+            let r = window.rect;
+            match direction {
+                'h' => {
+                    r.x + r.width + 1 == rect.x
+                        && r.y < rect.y + rect.height
+                        && rect.y < r.y + r.height
+                }
+                'l' => {
+                    rect.x + rect.width + 1 == r.x
+                        && r.y < rect.y + rect.height
+                        && rect.y < r.y + r.height
+                }
+                'k' => {
+                    r.y + r.height == rect.y && r.x < rect.x + rect.width && rect.x < r.x + r.width
+                }
+                'j' => {
+                    rect.y + rect.height == r.y
+                        && r.x < rect.x + rect.width
+                        && rect.x < r.x + r.width
+                }
+                _ => false,
+            }
+        });
+        let level = |window: &&Window| match direction {
+            'h' | 'l' => (window.rect.y..window.rect.y + window.rect.height).contains(&row),
+            _ => (window.rect.x..=window.rect.x + window.rect.width).contains(&col),
+        };
+
+        let candidates: Vec<&Window> = candidates.collect();
+        candidates
+            .iter()
+            .find(|window| level(window))
+            .or(candidates.first())
+            .map(|window| window.id)
+    }
+
+    /// Runs the Ctrl-W command `key`.
+    pub fn editor_window_command(&mut self, key: char, count: Option<usize>) {
+        let n = count.unwrap_or(1).max(1);
+        match key {
+            's' | 'S' => self.editor_split(SplitKind::Horizontal, None),
+            'v' => self.editor_split(SplitKind::Vertical, None),
+            'w' | 'W' => {
+                let idx = self
+                    .windows
+                    .iter()
+                    .position(|window| window.id == self.window)
+                    .unwrap();
+                let len = self.windows.len();
+                let next = match (key, count) {
+                    // A count goes to that window.
+                    (_, Some(count)) => count.clamp(1, len) - 1,
+                    ('w', None) => (idx + 1) % len,
+                    _ => (idx + len - 1) % len,
+                };
+                self.editor_focus_window(self.windows[next].id);
+            }
+            'h' | 'j' | 'k' | 'l' => {
+                for _ in 0..n {
+                    match self.editor_neighbor_window(key) {
+                        Some(id) => self.editor_focus_window(id),
+                        None => break,
+                    }
+                }
+            }
+            'c' | 'q' => self.editor_close_window(false),
+            'o' => self.editor_only_window(),
+            '+' | '-' | '>' | '<' => {
+                let kind = match key {
+                    '+' | '-' => SplitKind::Horizontal,
+                    _ => SplitKind::Vertical,
+                };
+                let delta = if matches!(key, '+' | '>') {
+                    n as i32
+                } else {
+                    -(n as i32)
+                };
+                self.layout.resize(self.window, kind, delta);
+                self.editor_layout();
+            }
+            '=' => {
+                self.layout.equalize();
+                self.editor_layout();
+            }
+            _ => {}
+        }
+    }
+
+    /// The window drawn at the screen cell `(x, y)`.
+    pub fn editor_window_at(&self, x: u16, y: u16) -> Option<usize> {
+        self.windows
+            .iter()
+            .find(|window| window.rect.contains(x, y))
+            .map(|window| window.id)
+    }
+
+    /// Draws every window, with the separators between them and their
+    /// status lines.
+    pub fn editor_draw_windows(&self, frame: &mut Frame) {
+        let status = self.editor_window_status();
+        for window in &self.windows {
+            let active = window.id == self.window;
+            let mut text = window.rect;
+            if status {
+                text.height = text.height.saturating_sub(1);
+            }
+
+            if active {
+                let offset = (self.rowoff, self.coloff);
+                let selection = self.editor_selection();
+                self.editor_draw_rows(frame, text, &self.buffer, offset, self.cy, selection);
+            } else {
+                let buffer = self.editor_buffer(window.buffer);
+                let cy = window.cursor.1;
+                self.editor_draw_rows(frame, text, buffer, window.offset, cy, None);
+            }
+
+            let rect = window.rect;
+            if rect.x + rect.width < frame.width {
+                for y in rect.y..rect.y + rect.height {
+                    frame.put(
+                        (rect.x + rect.width) as usize,
+                        y as usize,
+                        "│",
+                        1,
+                        self.theme.separator,
+                    );
+                }
+            }
+
+            if status && rect.height > 0 {
+                let buffer = if active {
+                    &self.buffer
+                } else {
+                    self.editor_buffer(window.buffer)
+                };
+                let style = if active {
+                    self.theme.status_line
+                } else {
+                    self.theme.status_line_inactive
+                };
+                let name = buffer.filename.as_deref().unwrap_or("[No Name]");
+                let dirty = if buffer.dirty { " [+]" } else { "" };
+                let y = (rect.y + rect.height - 1) as usize;
+                let end = (rect.x + rect.width) as usize;
+                let mut x = rect.x as usize;
+                for g in format!("{name}{dirty}").graphemes(true) {
+                    let width = g.width().max(1);
+                    if x + width > end {
+                        break;
+                    }
+                    frame.put(x, y, g, width, style);
+                    x += width;
+                }
+                for x in x..end {
+                    frame.put(x, y, " ", 1, style);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::editor::{EditorState, Erow};
+use crate::motion::{self, Motion};
+use crate::undo::Cursor;
+
+/// Render columns where each screen line of `render` starts when it wraps at
+/// `width` cells, with `indent` cells of break indicator before all lines but
+/// the first. With `linebreak`, lines end after the last blank that fits,
+/// when there is one.
+pub fn wrap_starts(render: &str, width: usize, indent: usize, linebreak: bool) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut start = 0;
+    let mut room = width.max(1);
+    // Render column after the last blank on the current line.
+    let mut blank = None;
+    let mut col = 0;
+    for g in render.graphemes(true) {
+        let w = g.width().max(1);
+        while col + w > start + room && col > start {
+            start = match blank {
+                Some(after) if linebreak && after > start => after,
+                _ => col,
+            };
+            starts.push(start);
+            room = width.saturating_sub(indent).max(1);
+            blank = None;
+        }
+        col += w;
+        if g.trim().is_empty() {
+            blank = Some(col);
+        }
+    }
+
+    starts
+}
+
+/// Index of the screen line the render column `rx` is on.
+fn line_of(starts: &[usize], rx: usize) -> usize {
+    starts
+        .partition_point(|start| *start <= rx)
+        .saturating_sub(1)
+}
+
+impl EditorState {
+    /// Cells `showbreak` takes at the start of continued lines in a window
+    /// `width` cells wide, or 0 when it would leave no room for text.
+    pub fn editor_showbreak_width(&self, width: usize) -> usize {
+        let w = self.config.showbreak.width();
+        if w < width {
+            w
+        } else {
+            0
+        }
+    }
+
+    /// Cells of break indicator before screen line `line` of a row.
+    fn editor_line_indent(&self, line: usize, width: usize) -> usize {
+        if line > 0 {
+            self.editor_showbreak_width(width)
+        } else {
+            0
+        }
+    }
+
+    /// Where the screen lines of `erow` start in a window `width` cells wide.
+    pub fn editor_wrap_starts(&self, erow: &Erow, width: usize) -> Vec<usize> {
+        let indent = self.editor_showbreak_width(width);
+        wrap_starts(&erow.render, width, indent, self.config.linebreak)
+    }
+
+    /// Where the screen lines of row `cy` of the current buffer start in the
+    /// current window. Rows past the end take a single line.
+    fn editor_row_starts(&self, cy: usize) -> Vec<usize> {
+        if cy >= self.buffer.row.len() {
+            return vec![0];
+        }
+        let width = self.editor_text_area().width as usize;
+        let erow = Erow::from(&self.buffer.row.line(cy), self.config.tabstop);
+        self.editor_wrap_starts(&erow, width)
+    }
+
+    /// Moves the wrapped view so the cursor line shows, with `scrolloff`
+    /// screen lines around it where the buffer has them, in a window `rows`
+    /// lines high.
+    pub fn editor_scroll_wrapped(&mut self, rows: usize, scrolloff: usize) {
+        self.coloff = 0;
+        let lines = |row: usize| self.editor_row_starts(row).len();
+        let (_, line) = self.editor_wrapped_cursor();
+
+        // Every row takes at least a line, so rows further than `rows` above
+        // the cursor row cannot be on screen along with it.
+        let mut rowoff = self.rowoff.clamp(self.cy.saturating_sub(rows), self.cy);
+        let mut above = line + (rowoff..self.cy).map(lines).sum::<usize>();
+        while rowoff > 0 && above < scrolloff {
+            rowoff -= 1;
+            above += lines(rowoff);
+        }
+
+        let mut below = lines(self.cy).saturating_sub(line + 1);
+        let mut row = self.cy + 1;
+        while below < scrolloff && row < self.buffer.row.len() {
+            below += lines(row);
+            row += 1;
+        }
+        let below = below.min(scrolloff);
+        while above + 1 + below > rows && rowoff < self.cy {
+            above -= lines(rowoff);
+            rowoff += 1;
+        }
+        self.rowoff = rowoff;
+    }
+
+    /// Last row of the current buffer whose screen lines all fit in the
+    /// first `lines` lines of the wrapped view, or `rowoff` when none does.
+    pub fn editor_wrapped_last_row(&self, lines: usize) -> usize {
+        let last = self.buffer.row.len().saturating_sub(1);
+        let mut row = self.rowoff;
+        let mut used = self.editor_row_starts(row).len();
+        while row < last {
+            used += self.editor_row_starts(row + 1).len();
+            if used > lines {
+                break;
+            }
+            row += 1;
+        }
+        row
+    }
+
+    /// Cell of the current window's text area the cursor is on, as
+    /// `(column, line)`.
+    pub fn editor_cursor_cell(&self) -> (usize, usize) {
+        let text = self.editor_text_area();
+        let (width, height) = (text.width as usize, text.height as usize);
+        // The view may not have scrolled to the cursor yet, as when a
+        // mapping moves it and switches windows in one go.
+        let (column, line) = if self.config.wrap {
+            let (column, line) = self.editor_wrapped_cursor();
+            let above: usize = (self.rowoff..self.cy)
+                .map(|row| self.editor_row_starts(row).len())
+                .sum();
+            (column, above + line)
+        } else {
+            (
+                self.rx.saturating_sub(self.coloff),
+                self.cy.saturating_sub(self.rowoff),
+            )
+        };
+        (
+            column.min(width.saturating_sub(1)),
+            line.min(height.saturating_sub(1)),
+        )
+    }
+
+    /// Cell and screen line of the cursor within its wrapped row. A cursor
+    /// just past a full last line goes to the start of the line below.
+    fn editor_wrapped_cursor(&self) -> (usize, usize) {
+        let width = self.editor_text_area().width as usize;
+        let starts = self.editor_row_starts(self.cy);
+        let line = line_of(&starts, self.rx);
+        let column = self.rx - starts[line] + self.editor_line_indent(line, width);
+        if column >= width {
+            (0, line + 1)
+        } else {
+            (column, line)
+        }
+    }
+
+    /// Buffer position drawn at `(column, line)` of the current window's
+    /// text area when wrapping. Past the last row is the last row.
+    pub fn editor_wrapped_cell_to_cursor(&self, column: usize, line: usize) -> Cursor {
+        let width = self.editor_text_area().width as usize;
+        let last = self.buffer.row.len() - 1;
+        let mut row = self.rowoff.min(last);
+        let mut line = line;
+        let mut starts = self.editor_row_starts(row);
+        while line >= starts.len() && row < last {
+            line -= starts.len();
+            row += 1;
+            starts = self.editor_row_starts(row);
+        }
+        let line = line.min(starts.len() - 1);
+        (
+            self.editor_line_column_to_cx(row, &starts, line, column, width),
+            row,
+        )
+    }
+
+    /// Column of the char shown at cell `column` of screen line `line` of
+    /// row `cy`, which starts its lines at `starts`.
+    fn editor_line_column_to_cx(
+        &self,
+        cy: usize,
+        starts: &[usize],
+        line: usize,
+        column: usize,
+        width: usize,
+    ) -> usize {
+        let erow = Erow::from(&self.buffer.row.line(cy), self.config.tabstop);
+        let indent = self.editor_line_indent(line, width);
+        let mut rx = starts[line] + column.saturating_sub(indent);
+        if let Some(next) = starts.get(line + 1) {
+            rx = rx.min(next - 1);
+        }
+        erow.editor_row_rx_to_cx(rx)
+    }
+
+    /// Position the cursor ends up at after moving by `motion`. With `wrap`
+    /// on, `gj` and `gk` go by screen lines, keeping the cursor's cell.
+    pub fn editor_motion_target(&self, motion: Motion, count: Option<usize>) -> Cursor {
+        let screen = matches!(motion, Motion::ScreenDown | Motion::ScreenUp);
+        if !screen || !self.config.wrap || self.buffer.row.is_empty() {
+            return motion::target(&self.buffer.row, (self.cx, self.cy), motion, count);
+        }
+
+        let width = self.editor_text_area().width as usize;
+        let last = self.buffer.row.len() - 1;
+        let mut row = self.cy.min(last);
+        let mut starts = self.editor_row_starts(row);
+        let rx = Erow::from(&self.buffer.row.line(row), self.config.tabstop)
+            .editor_row_cx_to_rx(self.cx);
+        let mut line = line_of(&starts, rx);
+        let indent = self.editor_line_indent(line, width);
+        let column = rx - starts[line] + indent;
+        for _ in 0..count.unwrap_or(1).max(1) {
+            if motion == Motion::ScreenDown {
+                if line + 1 < starts.len() {
+                    line += 1;
+                } else if row < last {
+                    row += 1;
+                    starts = self.editor_row_starts(row);
+                    line = 0;
+                }
+            } else if line > 0 {
+                line -= 1;
+            } else if row > 0 {
+                row -= 1;
+                starts = self.editor_row_starts(row);
+                line = starts.len() - 1;
+            }
+        }
+
+        let cx = self.editor_line_column_to_cx(row, &starts, line, column, width);
+        (cx.min(self.buffer.row.line_len(row)), row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hard_wrap() {
+        assert_eq!(wrap_starts("", 4, 0, false), vec![0]);
+        assert_eq!(wrap_starts("abcd", 4, 0, false), vec![0]);
+        assert_eq!(wrap_starts("abcdefghij", 4, 0, false), vec![0, 4, 8]);
+        assert_eq!(wrap_starts("abcdefghij", 4, 2, false), vec![0, 4, 6, 8]);
+    }
+
+    #[test]
+    fn double_width_at_edge() {
+        // A wide char that does not fit moves to the next line whole.
+        assert_eq!(wrap_starts("a世界", 4, 0, false), vec![0, 3]);
+        assert_eq!(wrap_starts("世界世", 3, 0, false), vec![0, 2, 4]);
+        // One too wide for any line still takes a line of its own.
+        assert_eq!(wrap_starts("世界", 1, 0, false), vec![0, 2]);
+    }
+
+    #[test]
+    fn linebreak() {
+        assert_eq!(wrap_starts("ab cd ef", 4, 0, false), vec![0, 4]);
+        assert_eq!(wrap_starts("ab cd ef", 4, 0, true), vec![0, 3, 6]);
+        // Without a blank to break after, lines break anywhere.
+        assert_eq!(wrap_starts("abcdefgh", 3, 0, true), vec![0, 3, 6]);
+        assert_eq!(wrap_starts("ab cdefgh", 3, 0, true), vec![0, 3, 6]);
+    }
+
+    #[test]
+    fn showbreak_wider_than_width() {
+        assert_eq!(wrap_starts("abcdef", 3, 5, false), vec![0, 3, 4, 5]);
+        assert_eq!(wrap_starts("abcdef", 3, 3, true), vec![0, 3, 4, 5]);
+    }
+
+    #[test]
+    fn tiny_widths() {
+        assert_eq!(wrap_starts("abc", 0, 0, false), vec![0, 1, 2]);
+        assert_eq!(wrap_starts("abc", 1, 0, false), vec![0, 1, 2]);
+        assert_eq!(wrap_starts("a b", 1, 0, true), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn line_of_column() {
+        let starts = [0, 4, 8];
+        assert_eq!(line_of(&starts, 0), 0);
+        assert_eq!(line_of(&starts, 3), 0);
+        assert_eq!(line_of(&starts, 4), 1);
+        assert_eq!(line_of(&starts, 20), 2);
+    }
+}